@@ -0,0 +1,61 @@
+// Each bench only uses some of these.
+#![allow(dead_code)]
+
+use std::time;
+
+use matryx_generator::{frame_tick::FrameTick, Canvas, Scene, UpdateRate};
+
+/// The panel sizes every benchmark runs at.
+pub const SIZES: [(u32, u32); 2] = [(64, 32), (128, 64)];
+
+pub fn size_name((width, height): (u32, u32)) -> String {
+    format!("{width}x{height}")
+}
+
+/// A tick `t` seconds in, one 30 fps frame after the last.
+pub fn tick(t: f32) -> FrameTick {
+    let instant = time::Instant::now();
+    FrameTick {
+        start: instant - time::Duration::from_secs_f32(t),
+        instant,
+        t,
+        dt: 1.0 / 30.0,
+        index: (t * 30.0) as u64,
+        target_dt: 1.0 / 30.0,
+    }
+}
+
+/// One update, the way `SceneRunner` drives the scene's `UpdateRate`.
+pub fn update(scene: &mut dyn Scene, canvas: &mut Canvas, tick: &FrameTick) {
+    match scene.update_rate() {
+        UpdateRate::EveryFrame | UpdateRate::Limited(_) => scene.tick(canvas, tick),
+        UpdateRate::FixedStep(hz) => {
+            scene.step(1.0 / hz);
+            scene.draw(canvas, tick, 0.5);
+        }
+    }
+}
+
+/// A smooth color field, like the wave scene produces.
+pub fn gradient(width: u32, height: u32) -> Canvas {
+    let mut canvas = Canvas::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let u = x as f32 / width as f32;
+            let v = y as f32 / height as f32;
+            canvas.set_pixel(x, y, u, v, 1.0 - u * v);
+        }
+    }
+    canvas
+}
+
+/// A mask covering every other column, like clock digits.
+pub fn mask(width: u32, height: u32) -> Canvas {
+    let mut canvas = Canvas::new(width, height);
+    for y in 0..height {
+        for x in (0..width).step_by(2) {
+            canvas.set_pixel(x, y, 1.0, 1.0, 1.0);
+        }
+    }
+    canvas
+}
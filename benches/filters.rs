@@ -0,0 +1,130 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use matryx_generator::{canvas, transform::Transform, Canvas};
+use palette::{FromColor, Hsl, IntoColor, Lch, ShiftHue, Srgb};
+
+mod common;
+
+use common::{gradient, mask, size_name, SIZES};
+
+// The per-pixel `palette` implementations the fast paths replaced, kept here
+// to measure against.
+mod reference {
+    use super::*;
+
+    fn color_lightness(pixel: [f32; 3], lightness: f32) -> Srgb {
+        let lch = Lch::from_color(Srgb::new(pixel[0], pixel[1], pixel[2]));
+        let mut hsl: Hsl = lch.into_color();
+        hsl.lightness *= lightness;
+        Srgb::from_color(hsl)
+    }
+
+    pub fn filter_hue_shift(canvas: &mut Canvas, shift: f32) {
+        for y in 0..canvas.height {
+            for x in 0..canvas.width {
+                let pixel = canvas.get_pixel(x, y);
+                let lch = Lch::from_color(Srgb::new(pixel[0], pixel[1], pixel[2])).shift_hue(shift);
+                let rgb = Srgb::from_color(lch);
+                canvas.set_pixel(x, y, rgb.red, rgb.green, rgb.blue);
+            }
+        }
+    }
+
+    pub fn filter_bright_background(canvas: &mut Canvas, canvas2: &mut Canvas, lightness: f32) {
+        for y in 0..canvas.height {
+            for x in 0..canvas.width {
+                let mask = canvas2.get_pixel(x, y);
+                if mask != [0.0; 3] {
+                    let rgb = color_lightness(canvas.get_pixel(x, y), lightness);
+                    canvas.set_pixel(x, y, rgb.red, rgb.green, rgb.blue);
+                }
+            }
+        }
+    }
+}
+
+fn hue_shift(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filter_hue_shift");
+    for (width, height) in SIZES {
+        let size = size_name((width, height));
+        let mut canvas = gradient(width, height);
+        group.bench_function(BenchmarkId::new("reference", &size), |b| {
+            b.iter(|| reference::filter_hue_shift(black_box(&mut canvas), 1.0))
+        });
+        group.bench_function(BenchmarkId::new("fast", &size), |b| {
+            b.iter(|| canvas::filter_hue_shift(black_box(&mut canvas), 1.0))
+        });
+    }
+    group.finish();
+}
+
+fn bright_background(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filter_bright_background");
+    for (width, height) in SIZES {
+        let size = size_name((width, height));
+        let mut mask = mask(width, height);
+        let canvas = gradient(width, height);
+        group.bench_function(BenchmarkId::new("reference", &size), |b| {
+            b.iter_batched_ref(
+                || canvas.clone(),
+                |canvas| reference::filter_bright_background(canvas, &mut mask, 0.1),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function(BenchmarkId::new("fast", &size), |b| {
+            b.iter_batched_ref(
+                || canvas.clone(),
+                |canvas| canvas::filter_bright_background(canvas, &mut mask, 0.1),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Every filter, on a copy of the same gradient and clock-like mask.
+fn filters(c: &mut Criterion) {
+    type Filter = fn(&mut Canvas, &mut Canvas);
+    let filters: [(&str, Filter); 11] = [
+        ("background", |c, m| canvas::filter_background(c, m)),
+        ("foreground", |c, m| canvas::filter_foreground(c, m)),
+        ("bright_foreground", |c, m| {
+            canvas::filter_bright_foreground(c, m, 0.1)
+        }),
+        ("bright_background", |c, m| {
+            canvas::filter_bright_background(c, m, 0.1)
+        }),
+        ("darken", |c, _| canvas::filter_darken(c, 0.5)),
+        ("red", |c, _| canvas::filter_red(c)),
+        ("quarter", |c, _| canvas::filter_quarter(c)),
+        ("hue_shift", |c, _| canvas::filter_hue_shift(c, 1.0)),
+        ("transform", |c, _| {
+            let transform = Transform {
+                flip_horizontal: true,
+                offset_x: 1,
+                ..Default::default()
+            };
+            canvas::filter_transform(c, &transform)
+        }),
+        ("rotate_left", |c, _| canvas::filter_rotate_left(c)),
+        ("rotate_right", |c, _| canvas::filter_rotate_right(c)),
+    ];
+
+    let mut group = c.benchmark_group("filter");
+    for size in SIZES {
+        let canvas = gradient(size.0, size.1);
+        let mut mask = mask(size.0, size.1);
+        for (name, filter) in filters {
+            group.bench_function(BenchmarkId::new(name, size_name(size)), |b| {
+                b.iter_batched_ref(
+                    || canvas.clone(),
+                    |canvas| filter(canvas, &mut mask),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hue_shift, bright_background, filters);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+mod common;
+
+use common::{size_name, tick, update, SIZES};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use matryx_generator::{
+    canvas,
+    scenes::{ClockScene, WaveScene},
+    Canvas, Scene,
+};
+
+/// One daytime frame as the main loop builds it: the clock and wave scenes,
+/// the hue shift, the clock cut out of the waves, and the encode for sending.
+fn frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    for (width, height) in SIZES {
+        let mut canvas_clock = Canvas::new(width, height);
+        let mut canvas_wave = Canvas::new(width, height);
+        let mut clock_scene = ClockScene::new(width, height);
+        let mut wave_scene = WaveScene::new(width, height);
+        let tick = tick(10.0);
+
+        group.bench_function(BenchmarkId::new("day", size_name((width, height))), |b| {
+            b.iter(|| {
+                update(&mut clock_scene as &mut dyn Scene, &mut canvas_clock, &tick);
+                update(&mut wave_scene as &mut dyn Scene, &mut canvas_wave, &tick);
+                canvas::filter_hue_shift(&mut canvas_wave, 1.0);
+                canvas::filter_bright_background(&mut canvas_wave, &mut canvas_clock, 0.1);
+                black_box(canvas_wave.to_srgb8())
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, frame);
+criterion_main!(benches);
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{size_name, tick, update, SIZES};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use matryx_generator::{
+    scenes::{
+        wave::{self, gen_weights},
+        ClockScene, PlasmaScene, SandScene, ShaderScene, WaveScene,
+    },
+    Canvas, Scene,
+};
+
+const SHADER: &str = "v = noise(x * 3, y * 2, t * 0.2) * 0.5 + 0.5; hsv(v + t * 0.1, 1, v)";
+
+/// One update of each scene, including its draw.
+fn scenes(c: &mut Criterion) {
+    type Build = fn(u32, u32) -> Box<dyn Scene>;
+    let scenes: [(&str, Build); 5] = [
+        ("wave", |w, h| Box::new(WaveScene::new(w, h))),
+        ("plasma", |w, h| Box::new(PlasmaScene::new(w, h))),
+        ("sand", |w, h| Box::new(SandScene::new(w, h))),
+        ("clock", |w, h| Box::new(ClockScene::new(w, h))),
+        ("shader", |w, h| {
+            Box::new(ShaderScene::new("shader", SHADER, w, h).unwrap())
+        }),
+    ];
+
+    let mut group = c.benchmark_group("scene");
+    for size in SIZES {
+        for (name, build) in scenes {
+            let mut canvas = Canvas::new(size.0, size.1);
+            let mut scene = build(size.0, size.1);
+            let tick = tick(10.0);
+            group.bench_function(BenchmarkId::new(name, size_name(size)), |b| {
+                b.iter(|| update(scene.as_mut(), black_box(&mut canvas), &tick))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Levels spread over `0.0..1.0` in a scattered order, like a settled wave map.
+fn wave_map(width: u32, height: u32) -> Vec<f32> {
+    (0..width * height)
+        .map(|i| (i as f32 * 0.618).fract())
+        .collect()
+}
+
+fn wave_steps(c: &mut Criterion) {
+    let weights = gen_weights(2);
+
+    let mut group = c.benchmark_group("wave");
+    for (width, height) in SIZES {
+        let map = wave_map(width, height);
+        group.bench_function(
+            BenchmarkId::new("median_filter", size_name((width, height))),
+            |b| b.iter(|| wave::median_filter(black_box(&map), width, height)),
+        );
+        // Every pixel, as a step does at worst.
+        group.bench_function(
+            BenchmarkId::new("grow_step", size_name((width, height))),
+            |b| {
+                b.iter(|| {
+                    for y in 0..height {
+                        for x in 0..width {
+                            black_box(wave::grow_step(
+                                x,
+                                y,
+                                &map,
+                                width,
+                                height,
+                                &weights,
+                                (0.4, 0.6),
+                            ));
+                        }
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, scenes, wave_steps);
+criterion_main!(benches);
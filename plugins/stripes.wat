@@ -0,0 +1,63 @@
+;; Diagonal stripes sliding across the canvas, as a minimal plugin. Build it
+;; with `wat2wasm stripes.wat`, from the WebAssembly Binary Toolkit. Plugins
+;; are usually written in a language that compiles to wasm32, like Rust or C.
+(module
+  (import "env" "param" (func $param (param i32 i32) (result f32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "speed")
+  ;; The pixels start after the parameter name.
+  (global $pixels i32 (i32.const 64))
+
+  (func (export "frame_buffer") (param $width i32) (param $height i32) (result i32)
+    (local $pages i32)
+    ;; Grow the memory until the pixels fit.
+    (local.set $pages
+      (i32.div_u
+        (i32.add
+          (i32.add
+            (global.get $pixels)
+            (i32.mul (i32.mul (local.get $width) (local.get $height)) (i32.const 4)))
+          (i32.const 65535))
+        (i32.const 65536)))
+    (if (i32.gt_u (local.get $pages) (memory.size))
+      (then (drop (memory.grow (i32.sub (local.get $pages) (memory.size))))))
+    (global.get $pixels))
+
+  (func (export "tick") (param $t f32) (param $dt f32) (param $width i32) (param $height i32)
+    (local $x i32)
+    (local $y i32)
+    (local $ptr i32)
+    (local $phase f32)
+    (local $v f32)
+    (local.set $phase (f32.mul (local.get $t) (call $param (i32.const 0) (i32.const 5))))
+    (local.set $ptr (global.get $pixels))
+    (block $rows_done
+      (loop $rows
+        (br_if $rows_done (i32.ge_u (local.get $y) (local.get $height)))
+        (local.set $x (i32.const 0))
+        (block $columns_done
+          (loop $columns
+            (br_if $columns_done (i32.ge_u (local.get $x) (local.get $width)))
+            ;; v = fract((x + y) / 16 - phase), a ramp repeating every 16 pixels.
+            (local.set $v
+              (f32.sub
+                (f32.div
+                  (f32.convert_i32_u (i32.add (local.get $x) (local.get $y)))
+                  (f32.const 16))
+                (local.get $phase)))
+            (local.set $v (f32.sub (local.get $v) (f32.floor (local.get $v))))
+            ;; From blue to red, opaque: bytes r, g, b, a.
+            (i32.store (local.get $ptr)
+              (i32.or
+                (i32.or
+                  (i32.trunc_f32_u (f32.mul (local.get $v) (f32.const 255)))
+                  (i32.const 0xff000000))
+                (i32.shl
+                  (i32.trunc_f32_u (f32.mul (f32.sub (f32.const 1) (local.get $v)) (f32.const 255)))
+                  (i32.const 16))))
+            (local.set $ptr (i32.add (local.get $ptr) (i32.const 4)))
+            (local.set $x (i32.add (local.get $x) (i32.const 1)))
+            (br $columns)))
+        (local.set $y (i32.add (local.get $y) (i32.const 1)))
+        (br $rows))))
+)
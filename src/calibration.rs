@@ -0,0 +1,425 @@
+use std::fs;
+
+use serde::Deserialize;
+
+// Entries in the largest .cube LUT loaded, one of 129 points per side. Common
+// sizes are 17, 33 and 65.
+const MAX_CUBE_ENTRIES: usize = 129 * 129 * 129;
+
+/// Color correction for one output, applied to the final wire frame just
+/// before it is sent. Panels from different batches can be matched by giving
+/// each its own calibration.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CalibrationConfig {
+    /// Exponent per channel, red, green and blue. HUB75 panels are close to
+    /// linear, so around 2.2 makes gradients look even.
+    pub gamma: [f32; 3],
+    /// Scale per channel after gamma, to correct the panel's white point.
+    pub white_point: [f32; 3],
+    /// Applied before gamma and white point.
+    pub lut: Option<LutConfig>,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        CalibrationConfig {
+            gamma: [1.0; 3],
+            white_point: [1.0; 3],
+            lut: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LutConfig {
+    /// A text file of 256 lines, each the `r g b` output for that input level.
+    /// Lines starting with `#` are ignored.
+    Channels { path: String },
+    /// A 3D LUT in the `.cube` format, sampled with trilinear interpolation.
+    Cube { path: String },
+}
+
+enum Lut {
+    Channels(Box<[[u8; 256]; 3]>),
+    Cube { size: usize, table: Vec<[f32; 3]> },
+}
+
+/// A `CalibrationConfig` with its LUT loaded, built once at startup.
+pub struct Calibration {
+    lut: Option<Lut>,
+    gamma: [f32; 3],
+    white_point: [f32; 3],
+}
+
+impl Calibration {
+    pub fn from_config(config: &CalibrationConfig) -> Result<Calibration, String> {
+        let lut = match &config.lut {
+            Some(LutConfig::Channels { path }) => Some(load_channels(path)?),
+            Some(LutConfig::Cube { path }) => Some(load_cube(path)?),
+            None => None,
+        };
+
+        Ok(Calibration {
+            lut,
+            gamma: config.gamma,
+            white_point: config.white_point,
+        })
+    }
+
+    /// Whether the calibration leaves every pixel as it is.
+    pub fn is_identity(&self) -> bool {
+        self.lut.is_none() && self.gamma == [1.0; 3] && self.white_point == [1.0; 3]
+    }
+
+    /// Corrects packed sRGB `pixels`, in `0.0..=1.0`, in place.
+    pub fn apply(&self, pixels: &mut [f32]) {
+        for pixel in pixels.chunks_exact_mut(3) {
+            let rgb = match &self.lut {
+                Some(Lut::Channels(tables)) => [
+                    lookup(&tables[0], pixel[0]),
+                    lookup(&tables[1], pixel[1]),
+                    lookup(&tables[2], pixel[2]),
+                ],
+                Some(Lut::Cube { size, table }) => {
+                    sample_cube(*size, table, [pixel[0], pixel[1], pixel[2]])
+                }
+                None => [pixel[0], pixel[1], pixel[2]],
+            };
+
+            for c in 0..3 {
+                pixel[c] = (rgb[c].clamp(0.0, 1.0).powf(self.gamma[c]) * self.white_point[c])
+                    .clamp(0.0, 1.0);
+            }
+        }
+    }
+}
+
+/// Looks up `v` in a 256 entry table, interpolating between entries.
+fn lookup(table: &[u8; 256], v: f32) -> f32 {
+    let pos = v.clamp(0.0, 1.0) * 255.0;
+    let i0 = pos as usize;
+    let i1 = (i0 + 1).min(255);
+    let (a, b) = (table[i0] as f32, table[i1] as f32);
+    (a + (b - a) * (pos - i0 as f32)) / 255.0
+}
+
+fn sample_cube(size: usize, table: &[[f32; 3]], rgb: [f32; 3]) -> [f32; 3] {
+    let max = (size - 1) as f32;
+    let mut i0 = [0; 3];
+    let mut i1 = [0; 3];
+    let mut f = [0.0; 3];
+    for c in 0..3 {
+        let pos = rgb[c].clamp(0.0, 1.0) * max;
+        i0[c] = (pos as usize).min(size - 1);
+        i1[c] = (i0[c] + 1).min(size - 1);
+        f[c] = pos - i0[c] as f32;
+    }
+
+    // Red changes fastest in a .cube table.
+    let at = |r: usize, g: usize, b: usize| table[(b * size + g) * size + r];
+    let lerp = |a: [f32; 3], b: [f32; 3], t: f32| {
+        [
+            a[0] + (b[0] - a[0]) * t,
+            a[1] + (b[1] - a[1]) * t,
+            a[2] + (b[2] - a[2]) * t,
+        ]
+    };
+
+    let [r0, g0, b0] = i0;
+    let [r1, g1, b1] = i1;
+    let c00 = lerp(at(r0, g0, b0), at(r1, g0, b0), f[0]);
+    let c10 = lerp(at(r0, g1, b0), at(r1, g1, b0), f[0]);
+    let c01 = lerp(at(r0, g0, b1), at(r1, g0, b1), f[0]);
+    let c11 = lerp(at(r0, g1, b1), at(r1, g1, b1), f[0]);
+    let c0 = lerp(c00, c10, f[1]);
+    let c1 = lerp(c01, c11, f[1]);
+    lerp(c0, c1, f[2])
+}
+
+fn load_channels(path: &str) -> Result<Lut, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut tables = [[0; 256]; 3];
+    let mut count = 0;
+
+    for (line_no, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if count == 256 {
+            return Err(format!("{path}:{}: more than 256 entries", line_no + 1));
+        }
+
+        let values: Vec<u8> = line
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|_| {
+                format!(
+                    "{path}:{}: expected three levels, got {line:?}",
+                    line_no + 1
+                )
+            })?;
+        if values.len() != 3 {
+            return Err(format!(
+                "{path}:{}: expected three levels, got {line:?}",
+                line_no + 1
+            ));
+        }
+        for c in 0..3 {
+            tables[c][count] = values[c];
+        }
+        count += 1;
+    }
+
+    if count != 256 {
+        return Err(format!("{path}: expected 256 entries, got {count}"));
+    }
+    Ok(Lut::Channels(Box::new(tables)))
+}
+
+/// How many entries a cube of `size` points per side has, if it isn't
+/// degenerate or over `MAX_CUBE_ENTRIES`.
+fn cube_entries(size: usize) -> Option<usize> {
+    if size < 2 {
+        return None;
+    }
+    size.checked_mul(size)?
+        .checked_mul(size)
+        .filter(|entries| *entries <= MAX_CUBE_ENTRIES)
+}
+
+fn load_cube(path: &str) -> Result<Lut, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut size = None;
+    let mut table = vec![];
+
+    for (line_no, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let triple = |values: &str| -> Result<[f32; 3], String> {
+            values
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<Vec<f32>, _>>()
+                .ok()
+                .and_then(|values| values.try_into().ok())
+                .ok_or_else(|| {
+                    format!(
+                        "{path}:{}: expected three values, got {line:?}",
+                        line_no + 1
+                    )
+                })
+        };
+
+        if let Some(value) = line.strip_prefix("LUT_3D_SIZE") {
+            let value = value
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|size| Some((size, cube_entries(size)?)))
+                .ok_or_else(|| format!("{path}:{}: invalid LUT_3D_SIZE", line_no + 1))?;
+            size = Some(value);
+        } else if let Some(values) = line.strip_prefix("DOMAIN_MIN") {
+            if triple(values)? != [0.0; 3] {
+                return Err(format!(
+                    "{path}:{}: only DOMAIN_MIN 0 0 0 is supported",
+                    line_no + 1
+                ));
+            }
+        } else if let Some(values) = line.strip_prefix("DOMAIN_MAX") {
+            if triple(values)? != [1.0; 3] {
+                return Err(format!(
+                    "{path}:{}: only DOMAIN_MAX 1 1 1 is supported",
+                    line_no + 1
+                ));
+            }
+        } else if line.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            // TITLE, and keywords other tools add.
+            continue;
+        } else {
+            table.push(triple(line)?);
+        }
+    }
+
+    let (size, entries) = size.ok_or_else(|| format!("{path}: missing LUT_3D_SIZE"))?;
+    if table.len() != entries {
+        return Err(format!(
+            "{path}: expected {entries} entries for size {size}, got {}",
+            table.len()
+        ));
+    }
+    Ok(Lut::Cube { size, table })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `contents` to a file of its own and loads it as a LUT.
+    fn load(name: &str, contents: &str) -> Result<Calibration, String> {
+        let path =
+            std::env::temp_dir().join(format!("matryx-calibration-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        let lut = if name.ends_with(".cube") {
+            LutConfig::Cube { path: path.clone() }
+        } else {
+            LutConfig::Channels { path: path.clone() }
+        };
+        let calibration = Calibration::from_config(&CalibrationConfig {
+            lut: Some(lut),
+            ..Default::default()
+        });
+        fs::remove_file(&path).ok();
+        calibration
+    }
+
+    fn channels(line: impl Fn(u32) -> String, count: u32) -> String {
+        (0..count).map(|i| line(i) + "\n").collect()
+    }
+
+    /// A .cube of the given size mapping each color through `f`.
+    fn cube(size: usize, f: impl Fn([f32; 3]) -> [f32; 3]) -> String {
+        let max = (size - 1) as f32;
+        let mut contents = format!("TITLE \"test\"\nLUT_3D_SIZE {size}\n");
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let [r, g, b] = f([r as f32 / max, g as f32 / max, b as f32 / max]);
+                    contents += &format!("{r} {g} {b}\n");
+                }
+            }
+        }
+        contents
+    }
+
+    fn applied(calibration: &Calibration, rgb: [f32; 3]) -> [f32; 3] {
+        let mut pixel = rgb;
+        calibration.apply(&mut pixel);
+        pixel
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4),
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn default_is_identity() {
+        let calibration = Calibration::from_config(&CalibrationConfig::default()).unwrap();
+        assert!(calibration.is_identity());
+        assert_eq!(applied(&calibration, [0.2, 0.5, 1.0]), [0.2, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn gamma_then_white_point() {
+        let calibration = Calibration::from_config(&CalibrationConfig {
+            gamma: [2.0, 1.0, 1.0],
+            white_point: [1.0, 0.5, 1.0],
+            lut: None,
+        })
+        .unwrap();
+        assert!(!calibration.is_identity());
+        assert_close(applied(&calibration, [0.5, 0.5, 0.5]), [0.25, 0.25, 0.5]);
+    }
+
+    #[test]
+    fn channel_tables() {
+        let calibration = load(
+            "inverted.txt",
+            &format!(
+                "# red inverted, green halved, blue as is\n{}",
+                channels(|i| format!("{} {} {i}", 255 - i, i / 2), 256)
+            ),
+        )
+        .unwrap();
+        assert_close(
+            applied(&calibration, [0.0, 1.0, 0.2]),
+            [1.0, 127.0 / 255.0, 0.2],
+        );
+        // Between entries, the neighbours are interpolated.
+        assert_close(
+            applied(&calibration, [0.5 / 255.0, 0.0, 0.0]),
+            [254.5 / 255.0, 0.0, 0.0],
+        );
+    }
+
+    #[test]
+    fn malformed_channel_tables_are_errors() {
+        let level = |i: u32| format!("{i} {i} {i}");
+        assert!(load("short.txt", &channels(level, 255)).is_err());
+        assert!(load("long.txt", &channels(level, 257)).is_err());
+        assert!(load("two.txt", &channels(|i| format!("{i} {i}"), 256)).is_err());
+        assert!(load(
+            "large.txt",
+            &channels(|i| format!("{i} {i} {}", i + 1), 256)
+        )
+        .is_err());
+        assert!(load("word.txt", &channels(|_| "a b c".to_string(), 256)).is_err());
+    }
+
+    #[test]
+    fn cubes() {
+        let identity = load("identity.cube", &cube(2, |rgb| rgb)).unwrap();
+        assert_close(applied(&identity, [0.2, 0.5, 0.9]), [0.2, 0.5, 0.9]);
+
+        let swapped = load("swapped.cube", &cube(3, |[r, g, b]| [b, r, g])).unwrap();
+        assert_close(applied(&swapped, [0.1, 0.4, 0.8]), [0.8, 0.1, 0.4]);
+        assert_close(applied(&swapped, [1.0, 1.0, 0.0]), [0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn malformed_cubes_are_errors() {
+        let valid = cube(2, |rgb| rgb);
+        let without_size = valid.replace("LUT_3D_SIZE 2\n", "");
+        assert!(load("without_size.cube", &without_size).is_err());
+        assert!(load("small.cube", &valid.replace("SIZE 2", "SIZE 1")).is_err());
+        assert!(load("size.cube", &valid.replace("SIZE 2", "SIZE two")).is_err());
+        assert!(load("mismatched.cube", &valid.replace("SIZE 2", "SIZE 3")).is_err());
+        assert!(load("short.cube", &format!("{valid}0 0\n")).is_err());
+        assert!(load("word.cube", &format!("{valid}0 0 x\n")).is_err());
+        assert!(load("huge.cube", &valid.replace("SIZE 2", "SIZE 130")).is_err());
+        let overflowing = valid.replace("SIZE 2", &format!("SIZE {}", usize::MAX / 2));
+        assert!(load("overflowing.cube", &overflowing).is_err());
+    }
+
+    #[test]
+    fn cubes_need_the_default_domain() {
+        let valid = cube(2, |rgb| rgb);
+        let domain = |min: &str, max: &str| {
+            valid.replace(
+                "LUT_3D_SIZE 2\n",
+                &format!("LUT_3D_SIZE 2\nDOMAIN_MIN {min}\nDOMAIN_MAX {max}\n"),
+            )
+        };
+        assert!(load("default_domain.cube", &domain("0 0 0", "1.0 1.0 1.0")).is_ok());
+        assert!(load("domain_min.cube", &domain("-1 0 0", "1 1 1")).is_err());
+        assert!(load("domain_max.cube", &domain("0 0 0", "2 2 2")).is_err());
+        assert!(load("domain_short.cube", &domain("0 0", "1 1 1")).is_err());
+    }
+
+    #[test]
+    fn missing_files_are_errors() {
+        for lut in [
+            LutConfig::Channels {
+                path: "/nonexistent/lut.txt".to_string(),
+            },
+            LutConfig::Cube {
+                path: "/nonexistent/lut.cube".to_string(),
+            },
+        ] {
+            let config = CalibrationConfig {
+                lut: Some(lut),
+                ..Default::default()
+            };
+            assert!(Calibration::from_config(&config).is_err());
+        }
+    }
+}
@@ -1,8 +1,7 @@
 use std::sync::{
-    atomic::{AtomicU8, Ordering},
+    atomic::{AtomicBool, AtomicU8, Ordering},
     Arc,
 };
-use std::thread::{self, sleep};
 use std::time;
 
 use image::{DynamicImage, ImageBuffer};
@@ -15,32 +14,43 @@ use v4l::Device;
 use v4l::FourCC;
 use v4l::{prelude::*, Format};
 
+use crate::lifecycle::sleep_unless_shutdown;
+use crate::metrics::Metrics;
+
 const CAMERA_FRAME_DELAY: time::Duration = time::Duration::from_millis(500);
 const RETRY_DELAY: time::Duration = time::Duration::from_secs(5);
 const MAX_ATTEMPTS: i8 = std::i8::MAX;
 const DEVICE_MISSING_ERROR: i32 = -1;
 const INVALID_BUFFER_PIXEL_FORMAT_ERROR: i32 = -2;
 
-pub fn cam_thread_loop(hists_clone: Arc<AtomicU8>) {
+pub fn cam_thread_loop(
+    hists_clone: Arc<AtomicU8>,
+    metrics: Arc<Metrics>,
+    shutdown: Arc<AtomicBool>,
+) {
     let mut attempt: i8 = 1;
-    let mut cam_thread_ret = cam_thread(hists_clone.clone(), attempt);
+    let mut cam_thread_ret = cam_thread(hists_clone.clone(), &metrics, &shutdown, attempt);
     loop {
         match cam_thread_ret {
             Ok(_) => {
-                warn!("unreachable");
+                warn!("Camera thread stopped");
+                return;
             }
             Err(e) => {
                 warn!("Camera Error: {e:?}");
+                metrics.inc_camera_restarts();
                 if attempt == MAX_ATTEMPTS {
                     attempt = 1;
                 } else {
                     attempt += 1;
                 }
-                cam_thread_ret = cam_thread(hists_clone.clone(), attempt);
+                if !sleep_unless_shutdown(&shutdown, RETRY_DELAY) {
+                    return;
+                }
+                error!("cam thread loop slept");
+                cam_thread_ret = cam_thread(hists_clone.clone(), &metrics, &shutdown, attempt);
             }
         }
-        sleep(RETRY_DELAY);
-        error!("cam thread loop slept")
     }
 }
 
@@ -56,7 +66,12 @@ fn set_device_format(dev: &mut Device, fourcc: FourCC) -> Result<Format, i32> {
     })
 }
 
-fn cam_thread(hists_clone: Arc<AtomicU8>, attempt: i8) -> Result<i32, i32> {
+fn cam_thread(
+    hists_clone: Arc<AtomicU8>,
+    metrics: &Metrics,
+    shutdown: &AtomicBool,
+    attempt: i8,
+) -> Result<i32, i32> {
     error!("Camera time, Attempt: {}\n", attempt);
 
     let mut dev = Device::new(0).map_err(|e| {
@@ -102,6 +117,9 @@ fn cam_thread(hists_clone: Arc<AtomicU8>, attempt: i8) -> Result<i32, i32> {
         let luma = DynamicImage::ImageRgb8(img).into_luma8();
         let val = percentile(&luma, 90);
         hists_clone.store(val, Ordering::Relaxed);
-        thread::sleep(CAMERA_FRAME_DELAY);
+        metrics.set_camera_reading(val);
+        if !sleep_unless_shutdown(shutdown, CAMERA_FRAME_DELAY) {
+            return Ok(0);
+        }
     }
 }
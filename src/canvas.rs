@@ -1,4 +1,16 @@
-use palette::{rgb::Rgb, FromColor, Hsl, IntoColor, Lch, ShiftHue, Srgb};
+use palette::{rgb::Rgb, FromColor, Hsl, IntoColor, Lch, Srgb};
+
+use image::{RgbImage, RgbaImage};
+
+use crate::{
+    color,
+    transform::{Rotation, Transform},
+};
+
+// Below this many pixels, splitting a filter across threads costs more than
+// it saves.
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_PIXELS: usize = 128 * 64;
 
 use embedded_graphics::{
     draw_target::DrawTarget,
@@ -9,11 +21,36 @@ use embedded_graphics::{
     Pixel,
 };
 
+/// Converts an sRGB encoded channel in `0.0..=1.0` to linear light.
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a linear light channel in `0.0..=1.0` to sRGB encoding.
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// An RGBA image in linear light, with straight (not premultiplied) alpha.
+///
+/// Scenes and filters work with sRGB encoded `0.0..=1.0` values through
+/// `set_pixel` and `get_pixel`, which convert at full precision, so nothing is
+/// quantized until the frame is encoded for output. Blending happens in linear
+/// light with `blend_pixel` and `composite`. A new canvas is transparent, and
+/// transparent pixels show as black on output.
 #[derive(Clone)]
 pub struct Canvas {
     pub width: u32,
     pub height: u32,
-    pub pixels: Vec<u8>,
+    pub pixels: Vec<[f32; 4]>,
 }
 
 impl Canvas {
@@ -21,44 +58,186 @@ impl Canvas {
         Canvas {
             width,
             height,
-            pixels: vec![0; (width * height * 3) as usize],
+            pixels: vec![[0.0; 4]; (width * height) as usize],
         }
     }
 
-    pub fn clear(&mut self) {
-        for pixel in self.pixels.iter_mut() {
-            *pixel = 0;
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some((y * self.width + x) as usize)
+        } else {
+            None
         }
     }
 
+    pub fn clear(&mut self) {
+        self.pixels.fill([0.0; 4]);
+    }
+
     pub fn clear_with_color(&mut self, r: f32, g: f32, b: f32) {
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let index = ((y * self.width + x) * 3) as usize;
-                self.pixels[index] = (r * 255.0) as u8;
-                self.pixels[index + 1] = (g * 255.0) as u8;
-                self.pixels[index + 2] = (b * 255.0) as u8;
+        let color = [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), 1.0];
+        self.pixels.fill(color);
+    }
+
+    /// Sets an opaque pixel from sRGB encoded channels. Pixels outside of the
+    /// canvas are ignored.
+    pub fn set_pixel(&mut self, x: u32, y: u32, r: f32, g: f32, b: f32) {
+        self.set(x, y, [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), 1.0]);
+    }
+
+    /// The sRGB encoded color of a pixel, over black. Pixels outside of the
+    /// canvas are black.
+    pub fn get_pixel(&self, x: u32, y: u32) -> [f32; 3] {
+        let [r, g, b, a] = self.get(x, y).unwrap_or_default();
+        [linear_to_srgb(r * a), linear_to_srgb(g * a), linear_to_srgb(b * a)]
+    }
+
+    /// The linear RGBA value of a pixel, or `None` outside of the canvas.
+    pub fn get(&self, x: u32, y: u32) -> Option<[f32; 4]> {
+        self.index(x, y).map(|i| self.pixels[i])
+    }
+
+    /// Sets a pixel to a linear RGBA value. Returns false, and does nothing,
+    /// outside of the canvas.
+    pub fn set(&mut self, x: u32, y: u32, rgba: [f32; 4]) -> bool {
+        match self.index(x, y) {
+            Some(i) => {
+                self.pixels[i] = rgba;
+                true
             }
+            None => false,
         }
     }
 
-    pub fn set_pixel(&mut self, x: u32, y: u32, r: f32, g: f32, b: f32) {
-        let index = ((y * self.width + x) * 3) as usize;
-        self.pixels[index] = (r * 255.0) as u8;
-        self.pixels[index + 1] = (g * 255.0) as u8;
-        self.pixels[index + 2] = (b * 255.0) as u8;
+    /// Draws a linear RGBA value over a pixel.
+    pub fn blend_pixel(&mut self, x: u32, y: u32, rgba: [f32; 4]) {
+        if let Some(i) = self.index(x, y) {
+            self.pixels[i] = blend(self.pixels[i], rgba);
+        }
+    }
+
+    /// Draws `top` over this canvas, which must be the same size.
+    pub fn composite(&mut self, top: &Canvas) {
+        assert_eq!((self.width, self.height), (top.width, top.height));
+        for (dst, src) in self.pixels.iter_mut().zip(&top.pixels) {
+            *dst = blend(*dst, *src);
+        }
+    }
+
+    /// Rows of linear RGBA pixels, from the top.
+    pub fn rows(&self) -> impl Iterator<Item = &[[f32; 4]]> {
+        self.pixels.chunks_exact(self.width.max(1) as usize)
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [[f32; 4]]> {
+        self.pixels.chunks_exact_mut(self.width.max(1) as usize)
+    }
+
+    /// Every pixel with its coordinates, row by row.
+    pub fn enumerate_pixels(&self) -> impl Iterator<Item = (u32, u32, &[f32; 4])> {
+        let width = self.width.max(1);
+        self.pixels
+            .iter()
+            .enumerate()
+            .map(move |(i, px)| (i as u32 % width, i as u32 / width, px))
+    }
+
+    pub fn enumerate_pixels_mut(&mut self) -> impl Iterator<Item = (u32, u32, &mut [f32; 4])> {
+        let width = self.width.max(1);
+        self.pixels
+            .iter_mut()
+            .enumerate()
+            .map(move |(i, px)| (i as u32 % width, i as u32 / width, px))
+    }
+
+    /// Encodes the canvas over black as packed sRGB channels in `0.0..=1.0`,
+    /// for output stages that work at full precision.
+    pub fn encode_srgb(&self, out: &mut Vec<f32>) {
+        out.clear();
+        out.extend(
+            self.pixels
+                .iter()
+                .flat_map(|[r, g, b, a]| [r * a, g * a, b * a])
+                .map(|v| linear_to_srgb(v.clamp(0.0, 1.0))),
+        );
+    }
+
+    /// Encodes the canvas over black as packed 8-bit sRGB, as sent to the
+    /// matrix.
+    pub fn to_srgb8(&self) -> Vec<u8> {
+        let mut encoded = vec![];
+        self.encode_srgb(&mut encoded);
+        encoded
+            .into_iter()
+            .map(|v| (v * 255.0).round() as u8)
+            .collect()
+    }
+
+    pub fn to_rgb_image(&self) -> RgbImage {
+        RgbImage::from_raw(self.width, self.height, self.to_srgb8()).unwrap()
+    }
+
+    pub fn to_rgba_image(&self) -> RgbaImage {
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let [r, g, b, a] = self.pixels[(y * self.width + x) as usize];
+            let encode = |v: f32| (linear_to_srgb(v.clamp(0.0, 1.0)) * 255.0).round() as u8;
+            image::Rgba([encode(r), encode(g), encode(b), (a.clamp(0.0, 1.0) * 255.0).round() as u8])
+        })
     }
+}
 
-    pub fn get_pixel(&mut self, x: u32, y: u32) -> [f32; 3] {
-        let index = ((y * self.width + x) * 3) as usize;
-        let r = self.pixels[index] as f32 / 255.0;
-        let g = self.pixels[index + 1] as f32 / 255.0;
-        let b = self.pixels[index + 2] as f32 / 255.0;
-        return [r as f32, g as f32, b as f32];
+#[cfg(test)]
+impl Canvas {
+    /// A canvas whose pixels hold their own index in red, to follow where
+    /// pixels end up.
+    pub fn numbered(width: u32, height: u32) -> Canvas {
+        let mut canvas = Canvas::new(width, height);
+        for (i, px) in canvas.pixels.iter_mut().enumerate() {
+            *px = [i as f32, 0.0, 0.0, 1.0];
+        }
+        canvas
     }
 
-    pub fn pixels(&self) -> &[u8] {
-        &self.pixels
+    /// The indices a `numbered` canvas's pixels were moved from, row by row.
+    pub fn indices(&self) -> Vec<Vec<i32>> {
+        self.rows()
+            .map(|row| row.iter().map(|px| px[0] as i32).collect())
+            .collect()
+    }
+}
+
+/// Source-over in linear light, with straight alpha.
+fn blend(dst: [f32; 4], src: [f32; 4]) -> [f32; 4] {
+    let alpha = src[3] + dst[3] * (1.0 - src[3]);
+    if alpha <= 0.0 {
+        return [0.0; 4];
+    }
+    let mut out = [0.0, 0.0, 0.0, alpha];
+    for c in 0..3 {
+        out[c] = (src[c] * src[3] + dst[c] * dst[3] * (1.0 - src[3])) / alpha;
+    }
+    out
+}
+
+impl From<&RgbImage> for Canvas {
+    fn from(img: &RgbImage) -> Self {
+        let mut canvas = Canvas::new(img.width(), img.height());
+        for (px, rgb) in canvas.pixels.iter_mut().zip(img.pixels()) {
+            let [r, g, b] = rgb.0.map(|v| srgb_to_linear(v as f32 / 255.0));
+            *px = [r, g, b, 1.0];
+        }
+        canvas
+    }
+}
+
+impl From<&RgbaImage> for Canvas {
+    fn from(img: &RgbaImage) -> Self {
+        let mut canvas = Canvas::new(img.width(), img.height());
+        for (px, rgba) in canvas.pixels.iter_mut().zip(img.pixels()) {
+            let [r, g, b, a] = rgba.0.map(|v| v as f32 / 255.0);
+            *px = [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a];
+        }
+        canvas
     }
 }
 
@@ -80,20 +259,27 @@ impl DrawTarget for Canvas {
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
         for px in pixels {
+            // Text and shapes can extend past the edges of small canvases.
+            if px.0.x < 0 || px.0.y < 0 {
+                continue;
+            }
             self.set_pixel(
                 px.0.x as u32,
                 px.0.y as u32,
-                px.1.r() as f32,
-                px.1.g() as f32,
-                px.1.b() as f32,
+                px.1.r() as f32 / 255.0,
+                px.1.g() as f32 / 255.0,
+                px.1.b() as f32 / 255.0,
             );
-            // self.set(px.0.x, px.0.y, &px.1.into());
         }
         Ok(())
     }
 
     fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
-        self.clear_with_color(color.r() as f32, color.g() as f32, color.b() as f32);
+        self.clear_with_color(
+            color.r() as f32 / 255.0,
+            color.g() as f32 / 255.0,
+            color.b() as f32 / 255.0,
+        );
         Ok(())
     }
 }
@@ -153,23 +339,23 @@ pub fn filter_bright_foreground(canvas: &mut Canvas, canvas2: &mut Canvas, light
     }
 }
 
+/// Darkens `canvas` by `lightness` wherever `canvas2` isn't black.
 pub fn filter_bright_background(canvas: &mut Canvas, canvas2: &mut Canvas, lightness: f32) {
-    for y in 0..canvas.height {
-        for x in 0..canvas.width {
-            let curr_pixel2 = canvas2.get_pixel(x, y);
-            let curr_pixel = canvas.get_pixel(x, y);
-            if curr_pixel2[0] == 0.0 && curr_pixel2[1] == 0.0 && curr_pixel2[2] == 0.0 {
-                // canvas.set_pixel(x, y, curr_pixel[0], curr_pixel[1], curr_pixel[2]);
-                // lighten?
-                // let my_rgb = color_lightness(curr_pixel, 1.0);
-                // canvas.set_pixel(x, y, my_rgb.red, /my_rgb.green, my_rgb.blue);
-            } else {
-                // darken
-                let my_rgb = color_lightness(curr_pixel, lightness);
-                canvas.set_pixel(x, y, my_rgb.red, my_rgb.green, my_rgb.blue);
+    let width = canvas.width as usize;
+    let mask = &canvas2.pixels;
+    for_each_row(canvas, |y, row| {
+        let mask_row = &mask[y * width..(y + 1) * width];
+        for (px, [mr, mg, mb, ma]) in row.iter_mut().zip(mask_row) {
+            if mr * ma == 0.0 && mg * ma == 0.0 && mb * ma == 0.0 {
+                continue;
             }
+            // Composited over black, like `get_pixel`.
+            let [r, g, b, a] = *px;
+            let srgb = [r * a, g * a, b * a].map(color::encode_srgb);
+            let [r, g, b] = color::scale_lightness(srgb, lightness).map(color::decode_srgb);
+            *px = [r, g, b, 1.0];
         }
-    }
+    });
 }
 
 pub fn filter_darken(canvas: &mut Canvas, lightness: f32) {
@@ -203,23 +389,50 @@ pub fn filter_quarter(canvas: &mut Canvas) {
     }
 }
 
+/// Shifts the hue of every pixel by `shift` degrees in CIE Lch.
 pub fn filter_hue_shift(canvas: &mut Canvas, shift: f32) {
-    for y in 0..canvas.height {
-        for x in 0..canvas.width {
-            let curr_pixel = canvas.get_pixel(x, y);
-
-            let my_rgb = Srgb::new(curr_pixel[0], curr_pixel[1], curr_pixel[2]);
-            let hue_shifted = Lch::from_color(my_rgb).shift_hue(shift);
-            let new_pixel = Srgb::from_color(hue_shifted);
-            canvas.set_pixel(x, y, new_pixel.red, new_pixel.green, new_pixel.blue);
+    let rotation = color::HueRotation::new(shift);
+    for_each_row(canvas, |_, row| {
+        for px in row {
+            let [r, g, b] = rotation.apply([px[0], px[1], px[2]]);
+            *px = [r, g, b, px[3]];
         }
+    });
+}
+
+/// Runs `f` on every row with its index, across threads for large canvases
+/// when built with the `parallel` feature.
+fn for_each_row<F>(canvas: &mut Canvas, f: F)
+where
+    F: Fn(usize, &mut [[f32; 4]]) + Send + Sync,
+{
+    let width = canvas.width.max(1) as usize;
+
+    #[cfg(feature = "parallel")]
+    if canvas.pixels.len() >= PARALLEL_MIN_PIXELS {
+        use rayon::prelude::*;
+        canvas
+            .pixels
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| f(y, row));
+        return;
+    }
+
+    for (y, row) in canvas.pixels.chunks_mut(width).enumerate() {
+        f(y, row);
     }
 }
 
+pub fn filter_transform(canvas: &mut Canvas, transform: &Transform) {
+    let src = canvas.clone();
+    transform.apply(&src, canvas);
+}
+
 pub fn filter_rotate_left(canvas: &mut Canvas) {
-    canvas.pixels.rotate_left(1);
+    filter_transform(canvas, &Transform::rotate(Rotation::R270));
 }
 
 pub fn filter_rotate_right(canvas: &mut Canvas) {
-    canvas.pixels.rotate_right(1);
+    filter_transform(canvas, &Transform::rotate(Rotation::R90));
 }
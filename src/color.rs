@@ -0,0 +1,237 @@
+use std::sync::OnceLock;
+
+use crate::canvas::{linear_to_srgb, srgb_to_linear};
+
+const LUT_SIZE: usize = 4096;
+
+// CIE Lab constants and the D65 white point, as used by `palette`.
+const EPSILON: f32 = 216.0 / 24389.0;
+const KAPPA: f32 = 24389.0 / 27.0;
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+const RGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.119192, 0.9503041],
+];
+const XYZ_TO_RGB: [[f32; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.969266, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+
+struct Luts {
+    encode: Vec<f32>,
+    decode: Vec<f32>,
+}
+
+fn luts() -> &'static Luts {
+    static LUTS: OnceLock<Luts> = OnceLock::new();
+    LUTS.get_or_init(|| {
+        let table = |f: fn(f32) -> f32| {
+            (0..=LUT_SIZE)
+                .map(|i| f(i as f32 / LUT_SIZE as f32))
+                .collect()
+        };
+        Luts {
+            encode: table(linear_to_srgb),
+            decode: table(srgb_to_linear),
+        }
+    })
+}
+
+fn lookup(table: &[f32], v: f32) -> f32 {
+    let pos = v.clamp(0.0, 1.0) * LUT_SIZE as f32;
+    let i = (pos as usize).min(LUT_SIZE - 1);
+    table[i] + (table[i + 1] - table[i]) * (pos - i as f32)
+}
+
+/// `linear_to_srgb` from a lookup table, for values in `0.0..=1.0`.
+pub fn encode_srgb(v: f32) -> f32 {
+    lookup(&luts().encode, v)
+}
+
+/// `srgb_to_linear` from a lookup table, for values in `0.0..=1.0`.
+pub fn decode_srgb(v: f32) -> f32 {
+    lookup(&luts().decode, v)
+}
+
+fn mul(m: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+fn lab_f(t: f32) -> f32 {
+    if t > EPSILON {
+        t.cbrt()
+    } else {
+        (KAPPA * t + 16.0) / 116.0
+    }
+}
+
+fn lab_f_inv(f: f32) -> f32 {
+    let cube = f * f * f;
+    if cube > EPSILON {
+        cube
+    } else {
+        (116.0 * f - 16.0) / KAPPA
+    }
+}
+
+/// Rotates hue in CIE Lch, as `Lch::shift_hue` does, by rotating the `a` and
+/// `b` axes of Lab with a precomputed sine and cosine instead of converting to
+/// polar coordinates and back. The result is within 1e-4 per sRGB channel of
+/// `palette`'s.
+pub struct HueRotation {
+    cos: f32,
+    sin: f32,
+}
+
+impl HueRotation {
+    pub fn new(degrees: f32) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        HueRotation { cos, sin }
+    }
+
+    /// Rotates a linear RGB color, clamped to `0.0..=1.0`.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let xyz = mul(&RGB_TO_XYZ, rgb);
+        let fx = lab_f(xyz[0] / WHITE[0]);
+        let fy = lab_f(xyz[1] / WHITE[1]);
+        let fz = lab_f(xyz[2] / WHITE[2]);
+
+        let a = 500.0 * (fx - fy);
+        let b = 200.0 * (fy - fz);
+        let a2 = a * self.cos - b * self.sin;
+        let b2 = a * self.sin + b * self.cos;
+
+        let fx = fy + a2 / 500.0;
+        let fz = fy - b2 / 200.0;
+        // Lightness is unchanged, so y is too.
+        let xyz = [lab_f_inv(fx) * WHITE[0], xyz[1], lab_f_inv(fz) * WHITE[2]];
+        mul(&XYZ_TO_RGB, xyz).map(|v| v.clamp(0.0, 1.0))
+    }
+}
+
+/// Scales HSL lightness by `factor`, as `color_lightness` does, in closed form.
+/// Keeping hue and saturation, each channel's distance from the lightness
+/// scales with the chroma the new lightness allows. The result is within 1e-4
+/// per channel of `palette`'s.
+pub fn scale_lightness(srgb: [f32; 3], factor: f32) -> [f32; 3] {
+    let max = srgb[0].max(srgb[1]).max(srgb[2]);
+    let min = srgb[0].min(srgb[1]).min(srgb[2]);
+    let lightness = (max + min) / 2.0;
+    let scaled = lightness * factor;
+
+    let chroma_range = 1.0 - (2.0 * lightness - 1.0).abs();
+    if chroma_range <= 0.0 {
+        return [scaled; 3];
+    }
+    let ratio = (1.0 - (2.0 * scaled - 1.0).abs()) / chroma_range;
+    srgb.map(|v| scaled + (v - lightness) * ratio)
+}
+
+/// Converts linear RGB to Oklab, as `[L, a, b]`.
+pub fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.41222147 * r + 0.53633254 * g + 0.05144599 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+    [
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+/// Converts Oklab back to linear RGB, without clamping to the gamut.
+pub fn oklab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = l + 0.39633778 * a + 0.21580376 * b;
+    let m_ = l - 0.105561346 * a - 0.06385417 * b;
+    let s_ = l - 0.08948418 * a - 1.2914855 * b;
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+    [
+        4.0767417 * l - 3.3077116 * m + 0.23096994 * s,
+        -1.268438 * l + 2.6097574 * m - 0.34131938 * s,
+        -0.0041960863 * l - 0.7034186 * m + 1.7076147 * s,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use palette::{FromColor, Hsl, IntoColor, Lch, ShiftHue, Srgb};
+
+    use super::*;
+
+    // Largest difference per channel from `palette` allowed of the fast
+    // paths, a small fraction of one 8-bit level.
+    const TOLERANCE: f32 = 1e-4;
+
+    /// sRGB colors spread over the whole cube.
+    fn colors() -> impl Iterator<Item = [f32; 3]> {
+        let steps = 16;
+        (0..=steps).flat_map(move |r| {
+            (0..=steps).flat_map(move |g| {
+                (0..=steps).map(move |b| [r, g, b].map(|c| c as f32 / steps as f32))
+            })
+        })
+    }
+
+    fn max_error(a: [f32; 3], b: [f32; 3]) -> f32 {
+        (0..3).map(|c| (a[c] - b[c]).abs()).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn lookup_tables_match_the_functions() {
+        for i in 0..=100_000 {
+            let v = i as f32 / 100_000.0;
+            assert!(
+                (encode_srgb(v) - linear_to_srgb(v)).abs() < TOLERANCE,
+                "{v}"
+            );
+            assert!(
+                (decode_srgb(v) - srgb_to_linear(v)).abs() < TOLERANCE,
+                "{v}"
+            );
+        }
+    }
+
+    #[test]
+    fn hue_rotation_matches_lch() {
+        for degrees in [1.0, 45.0, 90.0, 180.0, 300.0] {
+            let rotation = HueRotation::new(degrees);
+            for srgb in colors() {
+                let lch = Lch::from_color(Srgb::new(srgb[0], srgb[1], srgb[2])).shift_hue(degrees);
+                let reference = Srgb::from_color(lch);
+                let reference =
+                    [reference.red, reference.green, reference.blue].map(|v| v.clamp(0.0, 1.0));
+                let fast = rotation.apply(srgb.map(srgb_to_linear)).map(linear_to_srgb);
+                assert!(
+                    max_error(fast, reference) < TOLERANCE,
+                    "{srgb:?} by {degrees}: {fast:?} != {reference:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn scale_lightness_matches_hsl() {
+        for factor in [0.1, 0.5, 0.9, 1.0] {
+            for srgb in colors() {
+                // As `canvas::color_lightness` converts, by way of Lch.
+                let lch = Lch::from_color(Srgb::new(srgb[0], srgb[1], srgb[2]));
+                let mut hsl: Hsl = lch.into_color();
+                hsl.lightness *= factor;
+                let reference = Srgb::from_color(hsl);
+                let reference = [reference.red, reference.green, reference.blue];
+                let fast = scale_lightness(srgb, factor);
+                assert!(
+                    max_error(fast, reference) < TOLERANCE,
+                    "{srgb:?} by {factor}: {fast:?} != {reference:?}"
+                );
+            }
+        }
+    }
+}
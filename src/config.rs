@@ -0,0 +1,140 @@
+use std::{collections::BTreeMap, fs, io};
+
+use log2::*;
+use serde::Deserialize;
+
+use crate::{
+    calibration::CalibrationConfig,
+    dither::DitherConfig,
+    gradient::PaletteConfig,
+    layout::{LayoutConfig, Tile},
+    resample::Upscale,
+    scenes::{marquee::Message, ParamValue, PluginConfig, ScriptConfig},
+    transform::Transform,
+};
+
+const DEFAULT_CONFIG_PATH: &str = "matryx.toml";
+const DEFAULT_MATRIX_ADDR: &str = "tcp://localhost:42024";
+const DEFAULT_WIDTH: u32 = 64;
+const DEFAULT_HEIGHT: u32 = 32;
+const DEFAULT_DAY_SCENE: &str = "wave";
+
+/// Settings read from `matryx.toml`, or the file given as the first argument.
+/// See `matryx.example.toml` for every option.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Size of the logical canvas scenes render to.
+    pub width: u32,
+    pub height: u32,
+    pub outputs: Vec<OutputConfig>,
+    /// The scene shown behind the clock during the day: built in, a script, a
+    /// shader or a plugin.
+    pub day_scene: String,
+    /// Per-scene settings, by scene name.
+    pub scenes: BTreeMap<String, SceneConfig>,
+    /// User palettes, by the name scenes' palette parameters use.
+    pub palettes: BTreeMap<String, PaletteConfig>,
+    /// Rhai scenes, by name.
+    pub scripts: BTreeMap<String, ScriptConfig>,
+    /// Shader expression scenes, by name.
+    pub shaders: BTreeMap<String, String>,
+    /// WebAssembly scenes, by name.
+    pub plugins: BTreeMap<String, PluginConfig>,
+    /// Messages the marquee scene shows in turn.
+    pub marquee: Vec<Message>,
+}
+
+/// One `led_matrix_zmq` server and how its panel is mounted.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    pub addr: String,
+    /// The part of the canvas this output shows. Without one, it shows all
+    /// of it.
+    pub tile: Option<Tile>,
+    #[serde(default)]
+    pub transform: Transform,
+    /// How chained panels map to the canvas. Without one, the transformed
+    /// canvas is sent as is.
+    pub layout: Option<LayoutConfig>,
+    /// Color correction for this output's panels.
+    #[serde(default)]
+    pub calibration: CalibrationConfig,
+    #[serde(default)]
+    pub dither: DitherConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneConfig {
+    /// Resolution the scene renders at, relative to the canvas. 2.0 or 4.0
+    /// supersample, 0.5 renders at half resolution and scales up.
+    pub render_scale: f32,
+    pub upscale: Upscale,
+    /// Milliseconds an update may take. A scene over it several updates in a
+    /// row is turned off and restarted, like one that panics.
+    pub budget_ms: u64,
+    /// Initial values for the scene's parameters, by name.
+    pub params: BTreeMap<String, ParamValue>,
+}
+
+impl Default for SceneConfig {
+    fn default() -> Self {
+        SceneConfig {
+            render_scale: 1.0,
+            upscale: Upscale::default(),
+            budget_ms: 100,
+            params: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            outputs: vec![OutputConfig {
+                addr: DEFAULT_MATRIX_ADDR.to_string(),
+                tile: None,
+                transform: Transform::default(),
+                layout: None,
+                calibration: CalibrationConfig::default(),
+                dither: DitherConfig::default(),
+            }],
+            day_scene: DEFAULT_DAY_SCENE.to_string(),
+            scenes: BTreeMap::new(),
+            palettes: BTreeMap::new(),
+            scripts: BTreeMap::new(),
+            shaders: BTreeMap::new(),
+            plugins: BTreeMap::new(),
+            marquee: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn scene(&self, name: &str) -> SceneConfig {
+        self.scenes.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Loads the config. A missing default file falls back to the built-in
+    /// defaults, but a file that was asked for and can't be read is an error.
+    pub fn load() -> Result<Config, String> {
+        let arg = std::env::args().nth(1);
+        let path = arg.as_deref().unwrap_or(DEFAULT_CONFIG_PATH);
+
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                info!("Loading config from {}", path);
+                toml::from_str(&contents).map_err(|e| format!("{path}: {e}"))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound && arg.is_none() => {
+                info!("No {} found, using defaults", DEFAULT_CONFIG_PATH);
+                Ok(Config::default())
+            }
+            Err(e) => Err(format!("{path}: {e}")),
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, Sender},
+    thread, time,
+};
+
+use log2::*;
+use serde::Serialize;
+
+use crate::{
+    font, gradient,
+    notification::{Notification, Overlay},
+    scene_runner::SceneRunner,
+    scenes::{marquee, ParamSpec, ParamValue},
+    Scene,
+};
+
+// How long a request waits for the frame loop to pick it up.
+const REPLY_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+// How much longer the client waits, for a request picked up just before its
+// deadline to be answered.
+const REPLY_MARGIN: time::Duration = time::Duration::from_millis(500);
+const MAX_BODY_LEN: usize = 4096;
+
+enum Command {
+    List,
+    Palettes,
+    Fonts,
+    Get {
+        scene: String,
+    },
+    Set {
+        scene: String,
+        param: String,
+        value: String,
+    },
+    Marquee {
+        message: String,
+    },
+    Notify {
+        notification: String,
+    },
+    Dismiss,
+}
+
+struct Request {
+    command: Command,
+    // Past this the client has been told the frame loop isn't responding, so
+    // the command mustn't be applied after all.
+    deadline: time::Instant,
+    reply: Sender<Response>,
+}
+
+struct Response {
+    status: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn ok(body: String) -> Self {
+        Response {
+            status: "200 OK",
+            body,
+        }
+    }
+
+    fn error(status: &'static str, message: impl Into<String>) -> Self {
+        Response {
+            status,
+            body: serde_json::json!({ "error": message.into() }).to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SceneInfo {
+    name: &'static str,
+    description: &'static str,
+    params: Vec<ParamInfo>,
+}
+
+#[derive(Serialize)]
+struct ParamInfo {
+    #[serde(flatten)]
+    spec: &'static ParamSpec,
+    value: Option<ParamValue>,
+}
+
+impl SceneInfo {
+    fn new(scene: &dyn Scene) -> Self {
+        SceneInfo {
+            name: scene.name(),
+            description: scene.description(),
+            params: scene
+                .params()
+                .iter()
+                .map(|spec| ParamInfo {
+                    spec,
+                    value: scene.get_param(spec.name),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Requests from the control endpoint, waiting for the frame loop to apply
+/// them between frames, where the scenes aren't being rendered.
+pub struct Control {
+    rx: Receiver<Request>,
+}
+
+impl Control {
+    /// Answers every waiting request against `runners` and `overlay`, and
+    /// drops those that waited past their deadline.
+    pub fn handle_pending(&self, runners: &mut [&mut SceneRunner], overlay: &mut Overlay) {
+        while let Ok(request) = self.rx.try_recv() {
+            if time::Instant::now() > request.deadline {
+                continue;
+            }
+            let response = handle_command(request.command, runners, overlay);
+            let _ = request.reply.send(response);
+        }
+    }
+}
+
+fn find<'a>(runners: &'a mut [&mut SceneRunner], name: &str) -> Option<&'a mut dyn Scene> {
+    runners
+        .iter_mut()
+        .find(|runner| runner.scene().name() == name)
+        .map(|runner| runner.scene_mut())
+}
+
+fn handle_command(
+    command: Command,
+    runners: &mut [&mut SceneRunner],
+    overlay: &mut Overlay,
+) -> Response {
+    match command {
+        Command::List => {
+            let scenes: Vec<SceneInfo> = runners
+                .iter()
+                .map(|runner| SceneInfo::new(runner.scene()))
+                .collect();
+            Response::ok(serde_json::to_string(&scenes).unwrap())
+        }
+        Command::Palettes => Response::ok(serde_json::to_string(&gradient::names()).unwrap()),
+        Command::Fonts => Response::ok(serde_json::to_string(&font::names()).unwrap()),
+        Command::Get { scene } => match find(runners, &scene) {
+            Some(scene) => Response::ok(serde_json::to_string(&SceneInfo::new(scene)).unwrap()),
+            None => Response::error("404 Not Found", format!("unknown scene {scene}")),
+        },
+        Command::Set {
+            scene,
+            param,
+            value,
+        } => {
+            let scene = match find(runners, &scene) {
+                Some(scene) => scene,
+                None => return Response::error("404 Not Found", format!("unknown scene {scene}")),
+            };
+            let spec = match scene.params().iter().find(|spec| spec.name == param) {
+                Some(spec) => spec,
+                None => {
+                    return Response::error("404 Not Found", format!("unknown parameter {param}"))
+                }
+            };
+
+            match spec
+                .parse(&value)
+                .and_then(|value| scene.set_param(&param, value))
+            {
+                Ok(()) => {
+                    info!("Set {}.{} to {}", scene.name(), param, value.trim());
+                    let info = ParamInfo {
+                        spec,
+                        value: scene.get_param(&param),
+                    };
+                    Response::ok(serde_json::to_string(&info).unwrap())
+                }
+                Err(e) => Response::error("400 Bad Request", e),
+            }
+        }
+        Command::Marquee { message } => {
+            // Only a running marquee takes messages off the queue.
+            if find(runners, "marquee").is_none() {
+                return Response::error("409 Conflict", "the marquee scene isn't running");
+            }
+            let message: marquee::Message = match serde_json::from_str(&message) {
+                Ok(message) => message,
+                Err(e) => return Response::error("400 Bad Request", e.to_string()),
+            };
+            match marquee::enqueue(message) {
+                Ok(queued) => Response::ok(serde_json::json!({ "queued": queued }).to_string()),
+                Err(e) => Response::error("400 Bad Request", e),
+            }
+        }
+        Command::Notify { notification } => {
+            let notification: Notification = match serde_json::from_str(&notification) {
+                Ok(notification) => notification,
+                Err(e) => return Response::error("400 Bad Request", e.to_string()),
+            };
+            match overlay.push(notification) {
+                Ok(outcome) => Response::ok(
+                    serde_json::json!({ "outcome": outcome, "waiting": overlay.waiting() })
+                        .to_string(),
+                ),
+                Err(e) => Response::error("400 Bad Request", e),
+            }
+        }
+        Command::Dismiss => {
+            Response::ok(serde_json::json!({ "dismissed": overlay.dismiss() }).to_string())
+        }
+    }
+}
+
+/// Serves the control endpoint on `addr` from a background thread:
+///
+/// - `GET /scenes` lists every scene with its parameters and their values.
+/// - `GET /scenes/<scene>` describes one scene.
+/// - `PUT /scenes/<scene>/params/<param>` sets a parameter to the request
+///   body, like `2.5`, `true`, `0 0.5 1` for a color or `fire` for a palette.
+/// - `GET /palettes` lists the palette names palette parameters accept.
+/// - `GET /fonts` lists the font names font parameters accept.
+/// - `POST /marquee` queues a message for the marquee scene, given as JSON
+///   like `{"text": "Hello", "color": [1, 0.5, 0], "repeat": 2}`, and fails
+///   when the marquee isn't one of the scenes running.
+/// - `POST /notifications` shows a notification over every scene, given as
+///   JSON like `{"text": "Build failed", "priority": "high", "icon": "cross"}`.
+/// - `DELETE /notifications` dismisses the notification being shown.
+pub fn serve(addr: &str) -> Option<Control> {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind control endpoint {}: {}", addr, e);
+            return None;
+        }
+    };
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_connection(stream, &tx) {
+                        warn!("Control request failed: {}", e);
+                    }
+                }
+                Err(e) => warn!("Control connection failed: {}", e),
+            }
+        }
+    });
+
+    Some(Control { rx })
+}
+
+fn handle_connection(mut stream: TcpStream, tx: &Sender<Request>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(time::Duration::from_secs(1)))?;
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let response = if content_length > MAX_BODY_LEN {
+        Response::error("413 Payload Too Large", "body too large")
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        let body = String::from_utf8_lossy(&body).into_owned();
+
+        match parse_command(&method, &path, body) {
+            Some(command) => send_command(command, tx),
+            None => Response::error("404 Not Found", format!("no route for {method} {path}")),
+        }
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    )
+}
+
+fn parse_command(method: &str, path: &str, body: String) -> Option<Command> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["scenes"]) => Some(Command::List),
+        ("GET", ["palettes"]) => Some(Command::Palettes),
+        ("GET", ["fonts"]) => Some(Command::Fonts),
+        ("POST", ["marquee"]) => Some(Command::Marquee { message: body }),
+        ("POST", ["notifications"]) => Some(Command::Notify { notification: body }),
+        ("DELETE", ["notifications"]) => Some(Command::Dismiss),
+        ("GET", ["scenes", scene]) => Some(Command::Get {
+            scene: scene.to_string(),
+        }),
+        ("PUT" | "POST", ["scenes", scene, "params", param]) => Some(Command::Set {
+            scene: scene.to_string(),
+            param: param.to_string(),
+            value: body,
+        }),
+        _ => None,
+    }
+}
+
+fn send_command(command: Command, tx: &Sender<Request>) -> Response {
+    let (reply, rx) = mpsc::channel();
+    let request = Request {
+        command,
+        deadline: time::Instant::now() + REPLY_TIMEOUT,
+        reply,
+    };
+    if tx.send(request).is_err() {
+        return Response::error("503 Service Unavailable", "shutting down");
+    }
+    rx.recv_timeout(REPLY_TIMEOUT + REPLY_MARGIN)
+        .unwrap_or_else(|_| Response::error("503 Service Unavailable", "frame loop not responding"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notify(control: &Sender<Request>, deadline: time::Instant) -> Receiver<Response> {
+        let (reply, rx) = mpsc::channel();
+        let notification = r#"{"text": "Build failed"}"#.to_string();
+        control
+            .send(Request {
+                command: Command::Notify { notification },
+                deadline,
+                reply,
+            })
+            .unwrap();
+        rx
+    }
+
+    #[test]
+    fn requests_past_their_deadline_are_dropped() {
+        let (tx, rx) = mpsc::channel();
+        let control = Control { rx };
+        let mut overlay = Overlay::new(8, 8);
+        let now = time::Instant::now();
+
+        let late = notify(&tx, now - time::Duration::from_millis(1));
+        let on_time = notify(&tx, now + REPLY_TIMEOUT);
+        control.handle_pending(&mut [], &mut overlay);
+
+        assert!(late.try_recv().is_err());
+        assert_eq!(on_time.try_recv().unwrap().status, "200 OK");
+        assert_eq!(overlay.waiting(), 1);
+    }
+}
@@ -0,0 +1,153 @@
+use serde::Deserialize;
+
+// 4x4 Bayer matrix, as thresholds in 0..16.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DitherMode {
+    /// Round to the nearest level.
+    #[default]
+    None,
+    /// Carry each pixel's rounding error into the same pixel on the next
+    /// frame, so over a few frames it averages out to the exact level.
+    Temporal,
+    /// Threshold against a Bayer matrix that shifts every frame.
+    Ordered,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DitherConfig {
+    pub mode: DitherMode,
+    /// Scale by brightness here, before dithering, and send the server full
+    /// brightness. The server scales 8-bit levels down to a handful of steps
+    /// at low brightness, so this is what keeps dim gradients smooth.
+    pub software_brightness: bool,
+}
+
+/// Quantizes a full precision frame to the 8-bit levels sent to the server,
+/// keeping the per-pixel state dithering needs between frames.
+pub struct Quantizer {
+    mode: DitherMode,
+    error: Vec<f32>,
+    frame: usize,
+}
+
+impl Quantizer {
+    pub fn new(mode: DitherMode) -> Self {
+        Quantizer {
+            mode,
+            error: vec![],
+            frame: 0,
+        }
+    }
+
+    /// Quantizes packed RGB `src`, in `0.0..=1.0`, to `width` pixel wide rows
+    /// in `dst`.
+    pub fn quantize(&mut self, src: &[f32], width: u32, dst: &mut [u8]) {
+        if self.error.len() != src.len() {
+            self.error = vec![0.0; src.len()];
+        }
+        self.frame = self.frame.wrapping_add(1);
+
+        for (i, (value, out)) in src.iter().zip(dst.iter_mut()).enumerate() {
+            let level = value * 255.0;
+            *out = match self.mode {
+                DitherMode::None => level.round(),
+                DitherMode::Temporal => {
+                    let target = level + self.error[i];
+                    let quantized = target.round().clamp(0.0, 255.0);
+                    self.error[i] = target - quantized;
+                    quantized
+                }
+                DitherMode::Ordered => {
+                    let pixel = i / 3;
+                    let x = (pixel % width as usize + self.frame) % 4;
+                    let y = (pixel / width as usize + self.frame / 4) % 4;
+                    let threshold = (BAYER[y][x] as f32 + 0.5) / 16.0;
+                    (level + 0.5 - threshold).round()
+                }
+            }
+            .clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODES: [DitherMode; 3] = [DitherMode::None, DitherMode::Temporal, DitherMode::Ordered];
+
+    fn quantized(quantizer: &mut Quantizer, src: &[f32], width: u32) -> Vec<u8> {
+        let mut dst = vec![0; src.len()];
+        quantizer.quantize(src, width, &mut dst);
+        dst
+    }
+
+    #[test]
+    fn exact_levels_are_kept() {
+        let src: Vec<f32> = (0..=255).map(|level| level as f32 / 255.0).collect();
+        let expected: Vec<u8> = (0..=255).collect();
+        for mode in MODES {
+            let mut quantizer = Quantizer::new(mode);
+            for _ in 0..8 {
+                assert_eq!(quantized(&mut quantizer, &src, 16), expected, "{mode:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        for mode in MODES {
+            let mut quantizer = Quantizer::new(mode);
+            assert_eq!(
+                quantized(&mut quantizer, &[-0.5, 1.5, 2.0], 1),
+                [0, 255, 255]
+            );
+        }
+    }
+
+    #[test]
+    fn no_dithering_rounds() {
+        let mut quantizer = Quantizer::new(DitherMode::None);
+        let src = [100.4 / 255.0, 100.6 / 255.0, 0.1 / 255.0];
+        for _ in 0..4 {
+            assert_eq!(quantized(&mut quantizer, &src, 1), [100, 101, 0]);
+        }
+    }
+
+    #[test]
+    fn temporal_averages_over_frames() {
+        let mut quantizer = Quantizer::new(DitherMode::Temporal);
+        let src = [100.25 / 255.0, 0.5 / 255.0, 254.75 / 255.0];
+        let mut sums = [0u32; 3];
+        for _ in 0..4 {
+            for (sum, level) in sums.iter_mut().zip(quantized(&mut quantizer, &src, 1)) {
+                *sum += level as u32;
+            }
+        }
+        assert_eq!(sums, [401, 2, 1019]);
+    }
+
+    #[test]
+    fn ordered_averages_over_a_block() {
+        let mut quantizer = Quantizer::new(DitherMode::Ordered);
+        let src = [100.5 / 255.0; 4 * 4 * 3];
+        for _ in 0..4 {
+            let dst = quantized(&mut quantizer, &src, 4);
+            assert!(dst.iter().all(|level| (100..=101).contains(level)));
+            let sum: u32 = dst.iter().map(|level| *level as u32).sum();
+            assert_eq!(sum, 48 * 100 + 24);
+        }
+    }
+
+    #[test]
+    fn temporal_error_resets_with_the_frame_size() {
+        let mut quantizer = Quantizer::new(DitherMode::Temporal);
+        assert_eq!(quantized(&mut quantizer, &[0.4 / 255.0; 3], 1), [0; 3]);
+        // A carried error of 0.4 would round this one up.
+        assert_eq!(quantized(&mut quantizer, &[0.4 / 255.0; 6], 2), [0; 6]);
+    }
+}
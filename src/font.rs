@@ -0,0 +1,95 @@
+use embedded_graphics::prelude::*;
+use u8g2_fonts::{fonts::*, types::VerticalPosition, FontRenderer};
+
+// Every u8g2 font is its own type, so the ones selectable by name are listed
+// here. `_tf` fonts cover Latin-1, `unifont_t_*` other scripts and symbols,
+// `_tn` only digits and time punctuation.
+macro_rules! fonts {
+    ($($font:ident),* $(,)?) => {
+        const FONTS: &[(&str, fn() -> FontRenderer)] = &[
+            $((stringify!($font), FontRenderer::new::<$font>),)*
+        ];
+    };
+}
+
+fonts!(
+    u8g2_font_4x6_tf,
+    u8g2_font_5x7_tf,
+    u8g2_font_5x8_tf,
+    u8g2_font_6x10_tf,
+    u8g2_font_6x12_tf,
+    u8g2_font_6x13_tf,
+    u8g2_font_6x13B_tf,
+    u8g2_font_7x13_tf,
+    u8g2_font_7x13B_tf,
+    u8g2_font_7x14_tf,
+    u8g2_font_7x14B_tf,
+    u8g2_font_8x13_tf,
+    u8g2_font_8x13B_tf,
+    u8g2_font_9x15_tf,
+    u8g2_font_9x15B_tf,
+    u8g2_font_10x20_tf,
+    u8g2_font_tom_thumb_4x6_tf,
+    u8g2_font_profont10_tf,
+    u8g2_font_profont12_tf,
+    u8g2_font_profont17_tf,
+    u8g2_font_profont22_tf,
+    u8g2_font_profont29_tf,
+    u8g2_font_helvR08_tf,
+    u8g2_font_helvR10_tf,
+    u8g2_font_helvR12_tf,
+    u8g2_font_helvR14_tf,
+    u8g2_font_helvR18_tf,
+    u8g2_font_helvR24_tf,
+    u8g2_font_helvB08_tf,
+    u8g2_font_helvB10_tf,
+    u8g2_font_helvB12_tf,
+    u8g2_font_helvB14_tf,
+    u8g2_font_helvB18_tf,
+    u8g2_font_helvB24_tf,
+    u8g2_font_helvB14_tn,
+    u8g2_font_logisoso16_tf,
+    u8g2_font_logisoso20_tf,
+    u8g2_font_logisoso24_tf,
+    u8g2_font_logisoso32_tf,
+    u8g2_font_unifont_t_latin,
+    u8g2_font_unifont_t_greek,
+    u8g2_font_unifont_t_cyrillic,
+    u8g2_font_unifont_t_symbols,
+);
+
+const PREFIX: &str = "u8g2_font_";
+
+/// The font called `name`, with or without the `u8g2_font_` prefix, like
+/// `6x10_tf`. Characters the font doesn't have are skipped when drawing.
+pub fn named(name: &str) -> Option<FontRenderer> {
+    let name = name.strip_prefix(PREFIX).unwrap_or(name);
+    FONTS
+        .iter()
+        .find(|(font, _)| &font[PREFIX.len()..] == name)
+        .map(|(_, new)| new().with_ignore_unknown_chars(true))
+}
+
+/// The font called `name`, or `default` without one, for text whose fonts
+/// were already checked with `named`.
+pub fn checked(name: Option<&str>, default: &str) -> FontRenderer {
+    named(name.unwrap_or(default)).expect("fonts are validated")
+}
+
+/// `text` as a single line, with control characters replaced by spaces, and
+/// how far `font` advances drawing it.
+pub fn line(font: &FontRenderer, text: &str) -> (String, i32) {
+    let text = text.replace(char::is_control, " ");
+    let width = font
+        .get_rendered_dimensions(text.as_str(), Point::zero(), VerticalPosition::Center)
+        .map_or(0, |dimensions| dimensions.advance.x);
+    (text, width)
+}
+
+/// Names of every font `named` knows, without the prefix.
+pub fn names() -> Vec<&'static str> {
+    FONTS
+        .iter()
+        .map(|(font, _)| &font[PREFIX.len()..])
+        .collect()
+}
@@ -1,9 +1,23 @@
 use std::time;
 
-const FRAME_TIME: time::Duration = time::Duration::from_millis((1000.0 / 30.0) as u64);
+// With `CatchUp`, never render more than this many late frames back to back.
+const MAX_CATCH_UP_FRAMES: u64 = 5;
+
+/// What `FrameTimer` does when a frame finishes after the next deadline.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OverrunPolicy {
+    /// Drop every frame whose deadline has already passed and continue with
+    /// the most recent one, so the output stays in phase with the wall clock.
+    Skip,
+    /// Render the late frames back to back until the timer has caught up.
+    CatchUp,
+}
 
 pub struct FrameTimer {
+    fps: f64,
+    policy: OverrunPolicy,
     prev_tick: Option<FrameTick>,
+    next_index: u64,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -12,52 +26,138 @@ pub struct FrameTick {
     pub instant: time::Instant,
     pub t: f32,
     pub dt: f32,
+    /// Index of this frame's slot since start. Skipped frames still advance it.
+    pub index: u64,
+    /// The scheduled time between frames, `1 / fps`.
+    pub target_dt: f32,
+}
+
+/// Timing of a single frame, as measured by `FrameTimer::wait_for_next_frame`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameStats {
+    /// Time between the tick and the call to `wait_for_next_frame`.
+    pub busy: time::Duration,
+    pub slept: time::Duration,
+    /// How far past its deadline the next frame is starting.
+    pub late: time::Duration,
+    /// Frames dropped because of `OverrunPolicy::Skip` or the catch-up limit.
+    pub skipped: u64,
+}
+
+impl FrameStats {
+    pub fn overran(&self) -> bool {
+        !self.late.is_zero()
+    }
 }
 
 impl FrameTick {
-    fn new(start: time::Instant, instant: time::Instant, t: f32,dt: f32) -> FrameTick {
+    fn new(
+        start: time::Instant,
+        instant: time::Instant,
+        t: f32,
+        dt: f32,
+        index: u64,
+        target_dt: f32,
+    ) -> FrameTick {
         FrameTick {
             start,
             instant,
             t,
             dt,
+            index,
+            target_dt,
         }
     }
 
-    fn from_start() -> FrameTick {
+    fn from_start(target_dt: f32) -> FrameTick {
         let now = time::Instant::now();
-        FrameTick::new(now, now, 0.0, 0.0)
+        FrameTick::new(now, now, 0.0, 0.0, 0, target_dt)
     }
 
-    fn from_prev(last_tick: &FrameTick) -> FrameTick {
+    fn from_prev(last_tick: &FrameTick, index: u64) -> FrameTick {
         let start = last_tick.start;
         let instant = time::Instant::now();
         let t = start.elapsed().as_secs_f32();
         let dt = last_tick.instant.elapsed().as_secs_f32();
-        FrameTick::new(start, instant, t, dt)
+        FrameTick::new(start, instant, t, dt, index, last_tick.target_dt)
+    }
+}
+
+#[cfg(test)]
+impl FrameTick {
+    /// A tick `t` seconds in, at 30 fps. Every such tick shares one start, so
+    /// ticks made one after another are spaced by their `t`.
+    pub fn at(t: f32) -> FrameTick {
+        static START: std::sync::OnceLock<time::Instant> = std::sync::OnceLock::new();
+        let start = *START.get_or_init(time::Instant::now);
+        let dt = 1.0 / 30.0;
+        FrameTick::new(
+            start,
+            start + time::Duration::from_secs_f32(t),
+            t,
+            dt,
+            (t / dt) as u64,
+            dt,
+        )
     }
 }
 
 impl FrameTimer {
-    pub fn new() -> Self {
-        FrameTimer { prev_tick: None }
+    pub fn new(fps: f32, policy: OverrunPolicy) -> Self {
+        FrameTimer {
+            fps: fps as f64,
+            policy,
+            prev_tick: None,
+            next_index: 0,
+        }
     }
 
     pub fn tick(&mut self) -> FrameTick {
-        self.prev_tick = Some(match self.prev_tick {
-            None => FrameTick::from_start(),
-            Some(prev_tick) => FrameTick::from_prev(&prev_tick),
-        });
+        let tick = match self.prev_tick {
+            None => FrameTick::from_start((1.0 / self.fps) as f32),
+            Some(prev_tick) => FrameTick::from_prev(&prev_tick, self.next_index),
+        };
+        self.prev_tick = Some(tick);
+        self.next_index = tick.index + 1;
+
+        tick
+    }
 
-        self.prev_tick.unwrap()
+    // Deadlines are computed from the start instant rather than the previous
+    // frame so that render time and sleep overshoot never accumulate as drift.
+    fn deadline(&self, start: time::Instant, index: u64) -> time::Instant {
+        start + time::Duration::from_secs_f64(index as f64 / self.fps)
     }
 
-    pub fn wait_for_next_frame(&self) {
-        if let Some(prev_tick) = self.prev_tick {
-            let delta = prev_tick.instant.elapsed();
-            if delta < FRAME_TIME {
-                std::thread::sleep(FRAME_TIME - delta);
-            }
+    /// Sleeps until the next frame's deadline, applying the overrun policy if
+    /// that deadline has already passed.
+    pub fn wait_for_next_frame(&mut self) -> FrameStats {
+        let prev_tick = match self.prev_tick {
+            Some(prev_tick) => prev_tick,
+            None => return FrameStats::default(),
+        };
+
+        let now = time::Instant::now();
+        let deadline = self.deadline(prev_tick.start, self.next_index);
+        let mut stats = FrameStats {
+            busy: now - prev_tick.instant,
+            ..Default::default()
+        };
+
+        if now < deadline {
+            std::thread::sleep(deadline - now);
+            stats.slept = now.elapsed();
+            return stats;
         }
+
+        stats.late = now - deadline;
+        let behind = (stats.late.as_secs_f64() * self.fps) as u64;
+        stats.skipped = match self.policy {
+            OverrunPolicy::Skip => behind,
+            OverrunPolicy::CatchUp => behind.saturating_sub(MAX_CATCH_UP_FRAMES),
+        };
+        self.next_index += stats.skipped;
+
+        stats
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,396 @@
+use std::{
+    collections::BTreeMap,
+    f32::consts::TAU,
+    fs,
+    path::Path,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    canvas::{linear_to_srgb, srgb_to_linear},
+    color::{linear_to_oklab, oklab_to_linear},
+};
+
+const LUT_SIZE: usize = 256;
+// Samples taken from a cosine palette to build its stops.
+const COSINE_STOPS: usize = 64;
+
+/// A user palette, as listed under `[palettes]` in the config.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PaletteConfig {
+    /// A GIMP `.gpl` palette, a `.json` list of colors, or a `.png` strip whose
+    /// middle row is sampled left to right.
+    File { path: String },
+    /// `#rrggbb` colors spread evenly from start to end.
+    Colors { colors: Vec<String> },
+    /// `a + b * cos(2π * (c * t + d))` per channel, in sRGB.
+    Cosine {
+        a: [f32; 3],
+        b: [f32; 3],
+        c: [f32; 3],
+        d: [f32; 3],
+    },
+}
+
+/// A color at a position along a gradient, in Oklab.
+#[derive(Copy, Clone, Debug)]
+struct Stop {
+    position: f32,
+    lab: [f32; 3],
+}
+
+/// Colors along `0.0..=1.0`, interpolated in Oklab so blends between stops
+/// keep an even lightness instead of dipping through muddy grays.
+pub struct Gradient {
+    name: String,
+    stops: Vec<Stop>,
+    // Linear RGB, clamped to the gamut.
+    lut: Vec<[f32; 3]>,
+}
+
+impl Gradient {
+    /// Builds a gradient from sRGB colors at positions in `0.0..=1.0`.
+    pub fn new(name: &str, stops: &[(f32, [f32; 3])]) -> Result<Gradient, String> {
+        if stops.is_empty() {
+            return Err(format!("palette {name} has no colors"));
+        }
+
+        let mut stops: Vec<Stop> = stops
+            .iter()
+            .map(|(position, srgb)| Stop {
+                position: position.clamp(0.0, 1.0),
+                lab: linear_to_oklab(srgb.map(srgb_to_linear)),
+            })
+            .collect();
+        stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+
+        let mut gradient = Gradient {
+            name: name.to_string(),
+            stops,
+            lut: vec![],
+        };
+        gradient.lut = (0..LUT_SIZE)
+            .map(|i| {
+                let t = i as f32 / (LUT_SIZE - 1) as f32;
+                oklab_to_linear(gradient.sample_oklab(t)).map(|c| c.clamp(0.0, 1.0))
+            })
+            .collect();
+        Ok(gradient)
+    }
+
+    /// Builds a gradient from sRGB colors spread evenly from start to end.
+    pub fn even(name: &str, colors: &[[f32; 3]]) -> Result<Gradient, String> {
+        let last = colors.len().saturating_sub(1).max(1) as f32;
+        let stops: Vec<_> = colors
+            .iter()
+            .enumerate()
+            .map(|(i, srgb)| (i as f32 / last, *srgb))
+            .collect();
+        Gradient::new(name, &stops)
+    }
+
+    /// Builds a cosine palette, `a + b * cos(2π * (c * t + d))` per sRGB
+    /// channel, as described by Inigo Quilez.
+    pub fn cosine(
+        name: &str,
+        a: [f32; 3],
+        b: [f32; 3],
+        c: [f32; 3],
+        d: [f32; 3],
+    ) -> Result<Gradient, String> {
+        let colors: Vec<[f32; 3]> = (0..COSINE_STOPS)
+            .map(|i| {
+                let t = i as f32 / (COSINE_STOPS - 1) as f32;
+                let channel =
+                    |k: usize| (a[k] + b[k] * (TAU * (c[k] * t + d[k])).cos()).clamp(0.0, 1.0);
+                [channel(0), channel(1), channel(2)]
+            })
+            .collect();
+        Gradient::even(name, &colors)
+    }
+
+    pub fn from_config(name: &str, config: &PaletteConfig) -> Result<Gradient, String> {
+        match config {
+            PaletteConfig::File { path } => load_file(name, path),
+            PaletteConfig::Colors { colors } => {
+                let colors = colors
+                    .iter()
+                    .map(|hex| parse_hex(hex))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("palette {name}: {e}"))?;
+                Gradient::even(name, &colors)
+            }
+            PaletteConfig::Cosine { a, b, c, d } => Gradient::cosine(name, *a, *b, *c, *d),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The linear RGB color at `t`, clamped to `0.0..=1.0`. Fast enough to
+    /// call for every pixel.
+    pub fn sample(&self, t: f32) -> [f32; 3] {
+        let pos = t.clamp(0.0, 1.0) * (LUT_SIZE - 1) as f32;
+        let i = (pos as usize).min(LUT_SIZE - 2);
+        let f = pos - i as f32;
+        let (a, b) = (self.lut[i], self.lut[i + 1]);
+        [
+            a[0] + (b[0] - a[0]) * f,
+            a[1] + (b[1] - a[1]) * f,
+            a[2] + (b[2] - a[2]) * f,
+        ]
+    }
+
+    /// The Oklab color at `t`, clamped to `0.0..=1.0`, interpolated exactly
+    /// between stops.
+    pub fn sample_oklab(&self, t: f32) -> [f32; 3] {
+        let t = t.clamp(0.0, 1.0);
+        let next = self.stops.partition_point(|stop| stop.position < t);
+        if next == 0 {
+            return self.stops[0].lab;
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].lab;
+        }
+
+        let (a, b) = (self.stops[next - 1], self.stops[next]);
+        let span = b.position - a.position;
+        let f = if span > 0.0 {
+            (t - a.position) / span
+        } else {
+            0.0
+        };
+        [
+            a.lab[0] + (b.lab[0] - a.lab[0]) * f,
+            a.lab[1] + (b.lab[1] - a.lab[1]) * f,
+            a.lab[2] + (b.lab[2] - a.lab[2]) * f,
+        ]
+    }
+}
+
+/// Parses `#rrggbb`, with or without the `#`, to sRGB in `0.0..=1.0`.
+pub fn parse_hex(hex: &str) -> Result<[f32; 3], String> {
+    let digits = hex.trim().trim_start_matches('#');
+    let channel = |i: usize| {
+        digits
+            .get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .map(|c| c as f32 / 255.0)
+    };
+    match (digits.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!("invalid color {hex:?}, expected #rrggbb")),
+    }
+}
+
+fn load_file(name: &str, path: &str) -> Result<Gradient, String> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("gpl") => load_gpl(name, path),
+        Some("json") => load_json(name, path),
+        Some("png") => load_strip(name, path),
+        _ => Err(format!("{path}: expected a .gpl, .json or .png palette")),
+    }
+}
+
+/// Reads a GIMP palette: a `GIMP Palette` header, optional `Name:` and
+/// `Columns:` lines and `#` comments, then one `r g b [name]` line per color,
+/// each channel 0 to 255.
+fn load_gpl(name: &str, path: &str) -> Result<Gradient, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut lines = contents.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == "GIMP Palette" => {}
+        _ => return Err(format!("{path}: missing GIMP Palette header")),
+    }
+
+    let mut colors = vec![];
+    for (number, line) in lines {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+
+        let channels: Vec<u8> = line
+            .split_whitespace()
+            .take(3)
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("{path}:{}: expected r g b", number + 1))?;
+        match channels[..] {
+            [r, g, b] => colors.push([r, g, b].map(|c| c as f32 / 255.0)),
+            _ => return Err(format!("{path}:{}: expected r g b", number + 1)),
+        }
+    }
+    Gradient::even(name, &colors)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonPalette {
+    /// `["#ff0000", "#0000ff"]`, spread evenly.
+    Colors(Vec<String>),
+    /// `[{"position": 0.0, "color": "#ff0000"}, ...]`.
+    Stops(Vec<JsonStop>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonStop {
+    position: f32,
+    color: String,
+}
+
+/// Reads a JSON list of `#rrggbb` colors spread evenly, or of
+/// `{"position": 0.5, "color": "#rrggbb"}` stops.
+fn load_json(name: &str, path: &str) -> Result<Gradient, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let palette: JsonPalette =
+        serde_json::from_str(&contents).map_err(|e| format!("{path}: {e}"))?;
+    let result = match palette {
+        JsonPalette::Colors(colors) => colors
+            .iter()
+            .map(|hex| parse_hex(hex))
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|colors| Gradient::even(name, &colors)),
+        JsonPalette::Stops(stops) => stops
+            .iter()
+            .map(|stop| parse_hex(&stop.color).map(|color| (stop.position, color)))
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|stops| Gradient::new(name, &stops)),
+    };
+    result.map_err(|e| format!("{path}: {e}"))
+}
+
+/// Samples the middle row of an image, one stop per column.
+fn load_strip(name: &str, path: &str) -> Result<Gradient, String> {
+    let image = image::open(path)
+        .map_err(|e| format!("{path}: {e}"))?
+        .into_rgb8();
+    let y = image.height() / 2;
+    let colors: Vec<[f32; 3]> = (0..image.width())
+        .map(|x| image.get_pixel(x, y).0.map(|c| c as f32 / 255.0))
+        .collect();
+    Gradient::even(name, &colors).map_err(|e| format!("{path}: {e}"))
+}
+
+fn builtin() -> Vec<Gradient> {
+    let hex = |colors: &[&str]| -> Vec<[f32; 3]> {
+        colors.iter().map(|c| parse_hex(c).unwrap()).collect()
+    };
+    // A full turn of Oklch hue at even lightness and chroma.
+    let rainbow: Vec<[f32; 3]> = (0..=36)
+        .map(|i| {
+            let (sin, cos) = (i as f32 * 10.0).to_radians().sin_cos();
+            oklab_to_linear([0.75, 0.12 * cos, 0.12 * sin])
+                .map(|c| linear_to_srgb(c.clamp(0.0, 1.0)))
+        })
+        .collect();
+
+    let gradients = [
+        Gradient::even("rainbow", &rainbow),
+        Gradient::even("grayscale", &hex(&["#000000", "#ffffff"])),
+        Gradient::even(
+            "fire",
+            &hex(&[
+                "#000000", "#5c0a00", "#c42a00", "#ff8a00", "#ffd640", "#ffffff",
+            ]),
+        ),
+        Gradient::even(
+            "ocean",
+            &hex(&["#000814", "#003566", "#0077b6", "#00b4d8", "#90e0ef"]),
+        ),
+        Gradient::even(
+            "sunset",
+            &hex(&["#1a0533", "#6b1d5c", "#c43a5b", "#f2784b", "#ffc857"]),
+        ),
+        Gradient::even(
+            "forest",
+            &hex(&["#0b1f0b", "#1e5128", "#4e9f3d", "#a7d129", "#f0f5b0"]),
+        ),
+        Gradient::even(
+            "viridis",
+            &hex(&[
+                "#440154", "#482878", "#3e4989", "#31688e", "#26828e", "#1f9e89", "#35b779",
+                "#6ece58", "#b5de2b", "#fde725",
+            ]),
+        ),
+        Gradient::even(
+            "inferno",
+            &hex(&[
+                "#000004", "#1b0c41", "#4a0c6b", "#781c6d", "#a52c60", "#cf4446", "#ed6925",
+                "#fb9b06", "#f7d13d", "#fcffa4",
+            ]),
+        ),
+        Gradient::cosine(
+            "cosine_rainbow",
+            [0.5, 0.5, 0.5],
+            [0.5, 0.5, 0.5],
+            [1.0, 1.0, 1.0],
+            [0.0, 0.33, 0.67],
+        ),
+        Gradient::cosine(
+            "candy",
+            [0.5, 0.5, 0.5],
+            [0.5, 0.5, 0.5],
+            [1.0, 1.0, 1.0],
+            [0.0, 0.1, 0.2],
+        ),
+        Gradient::cosine(
+            "desert",
+            [0.8, 0.5, 0.4],
+            [0.2, 0.4, 0.2],
+            [2.0, 1.0, 1.0],
+            [0.0, 0.25, 0.25],
+        ),
+    ];
+    gradients.into_iter().map(Result::unwrap).collect()
+}
+
+fn registry() -> &'static RwLock<BTreeMap<String, Arc<Gradient>>> {
+    static REGISTRY: OnceLock<RwLock<BTreeMap<String, Arc<Gradient>>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let gradients = builtin()
+            .into_iter()
+            .map(|gradient| (gradient.name.clone(), Arc::new(gradient)))
+            .collect();
+        RwLock::new(gradients)
+    })
+}
+
+/// Looks up a built-in or loaded palette by name.
+pub fn named(name: &str) -> Option<Arc<Gradient>> {
+    registry().read().unwrap().get(name).cloned()
+}
+
+/// Names of every built-in and loaded palette.
+pub fn names() -> Vec<String> {
+    registry().read().unwrap().keys().cloned().collect()
+}
+
+/// Adds a palette, replacing any with the same name.
+pub fn register(gradient: Gradient) {
+    registry()
+        .write()
+        .unwrap()
+        .insert(gradient.name.clone(), Arc::new(gradient));
+}
+
+/// Loads the user palettes from the config, so scenes can use them by name.
+pub fn load_all(palettes: &BTreeMap<String, PaletteConfig>) -> Result<(), String> {
+    for (name, config) in palettes {
+        register(Gradient::from_config(name, config)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,425 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::{transform::Rotation, Canvas};
+
+// Largest wire a layout can describe. Its size comes from the config or a LUT
+// file, and is checked against this before anything is allocated for it.
+const MAX_WIRE_PIXELS: usize = 1 << 20;
+
+/// How the panels of one output are chained, relative to the logical canvas.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LayoutConfig {
+    /// Panels listed in chain order, each placed on the logical canvas.
+    Panels {
+        panel_width: u32,
+        panel_height: u32,
+        panels: Vec<PanelPlacement>,
+    },
+    /// A grid chained row by row from the top left, where every other row
+    /// runs right to left with its panels mounted upside down. Two rows is
+    /// the usual U-shape.
+    Serpentine {
+        panel_width: u32,
+        panel_height: u32,
+        columns: u32,
+        rows: u32,
+    },
+    /// A lookup table file. The first line is the wire width and height,
+    /// followed by one `x y` logical coordinate per wire pixel in wire order,
+    /// or `-` for a pixel that stays black. Lines starting with `#` are ignored.
+    Lut { path: String },
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PanelPlacement {
+    /// Top left corner of the panel on the logical canvas.
+    pub x: u32,
+    pub y: u32,
+    /// Clockwise rotation of the panel as mounted.
+    #[serde(default)]
+    pub rotation: Rotation,
+}
+
+/// The region of the logical canvas shown by one output, so several matrix
+/// servers can each drive part of one large canvas.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        // A tile so far out that its edge overflows doesn't fit either.
+        self.x
+            .checked_add(self.width)
+            .is_some_and(|right| right <= width)
+            && self
+                .y
+                .checked_add(self.height)
+                .is_some_and(|bottom| bottom <= height)
+    }
+
+    /// Copies the tile's region of `src` into `dst`, resizing `dst` if needed.
+    pub fn apply(&self, src: &Canvas, dst: &mut Canvas) {
+        if dst.width != self.width || dst.height != self.height {
+            *dst = Canvas::new(self.width, self.height);
+        }
+
+        let x = self.x as usize;
+        for (src_row, dst_row) in src.rows().skip(self.y as usize).zip(dst.rows_mut()) {
+            dst_row.copy_from_slice(&src_row[x..x + self.width as usize]);
+        }
+    }
+}
+
+/// Maps each pixel of the wire buffer, where chained panels sit side by side
+/// in chain order, to the logical canvas pixel it shows.
+pub struct PixelMap {
+    wire_width: u32,
+    wire_height: u32,
+    sources: Vec<Option<(u32, u32)>>,
+}
+
+impl PixelMap {
+    pub fn from_config(config: &LayoutConfig) -> Result<PixelMap, String> {
+        match config {
+            LayoutConfig::Panels {
+                panel_width,
+                panel_height,
+                panels,
+            } => PixelMap::from_panels(*panel_width, *panel_height, panels),
+            LayoutConfig::Serpentine {
+                panel_width,
+                panel_height,
+                columns,
+                rows,
+            } => {
+                let count = columns
+                    .checked_mul(*rows)
+                    .filter(|count| *count as usize <= MAX_WIRE_PIXELS);
+                if count.is_none() {
+                    return Err(format!("{columns}x{rows} panels is too many"));
+                }
+                let panels = serpentine(*panel_width, *panel_height, *columns, *rows);
+                PixelMap::from_panels(*panel_width, *panel_height, &panels)
+            }
+            LayoutConfig::Lut { path } => PixelMap::from_lut(path),
+        }
+    }
+
+    fn from_panels(
+        panel_width: u32,
+        panel_height: u32,
+        panels: &[PanelPlacement],
+    ) -> Result<PixelMap, String> {
+        let wire_width = u32::try_from(panels.len())
+            .ok()
+            .and_then(|count| count.checked_mul(panel_width))
+            .ok_or_else(|| format!("{} panels {panel_width} wide is too wide", panels.len()))?;
+        let wire_height = panel_height;
+        let mut sources = vec![None; wire_pixels(wire_width, wire_height)?];
+
+        for (chain_index, panel) in panels.iter().enumerate() {
+            for py in 0..panel_height {
+                for px in 0..panel_width {
+                    let (x, y) = match panel.rotation {
+                        Rotation::R0 => (px, py),
+                        Rotation::R90 => (panel_height - 1 - py, px),
+                        Rotation::R180 => (panel_width - 1 - px, panel_height - 1 - py),
+                        Rotation::R270 => (py, panel_width - 1 - px),
+                    };
+                    let source = panel
+                        .x
+                        .checked_add(x)
+                        .zip(panel.y.checked_add(y))
+                        .ok_or_else(|| {
+                            format!("panel at {}, {} is out of range", panel.x, panel.y)
+                        })?;
+                    let wire_x = chain_index as u32 * panel_width + px;
+                    sources[(py * wire_width + wire_x) as usize] = Some(source);
+                }
+            }
+        }
+
+        Ok(PixelMap {
+            wire_width,
+            wire_height,
+            sources,
+        })
+    }
+
+    fn from_lut(path: &str) -> Result<PixelMap, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let mut lines = contents
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        let parse_pair = |line_no: usize, line: &str| -> Result<(u32, u32), String> {
+            let mut parts = line.split_whitespace().map(str::parse::<u32>);
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(Ok(a)), Some(Ok(b)), None) => Ok((a, b)),
+                _ => Err(format!(
+                    "{path}:{line_no}: expected two numbers, got {line:?}"
+                )),
+            }
+        };
+
+        let (wire_width, wire_height) = match lines.next() {
+            Some((line_no, line)) => parse_pair(line_no, line)?,
+            None => return Err(format!("{path}: empty lookup table")),
+        };
+
+        let expected = wire_pixels(wire_width, wire_height).map_err(|e| format!("{path}: {e}"))?;
+        let mut sources = vec![];
+        for (line_no, line) in lines {
+            if line == "-" {
+                sources.push(None);
+            } else {
+                sources.push(Some(parse_pair(line_no, line)?));
+            }
+        }
+
+        if sources.len() != expected {
+            return Err(format!(
+                "{path}: expected {expected} entries for a {wire_width}x{wire_height} wire, got {}",
+                sources.len()
+            ));
+        }
+
+        Ok(PixelMap {
+            wire_width,
+            wire_height,
+            sources,
+        })
+    }
+
+    /// Size of the logical canvas the map reads from.
+    pub fn logical_size(&self) -> (u32, u32) {
+        self.sources
+            .iter()
+            .flatten()
+            .fold((0, 0), |(w, h), (x, y)| {
+                (w.max(x.saturating_add(1)), h.max(y.saturating_add(1)))
+            })
+    }
+
+    /// Writes `src` into `wire` in chain order, resizing `wire` if needed.
+    /// Wire pixels that map outside of `src` are black.
+    pub fn apply(&self, src: &Canvas, wire: &mut Canvas) {
+        if wire.width != self.wire_width || wire.height != self.wire_height {
+            *wire = Canvas::new(self.wire_width, self.wire_height);
+        }
+
+        for (px, source) in wire.pixels.iter_mut().zip(&self.sources) {
+            *px = match source {
+                Some((x, y)) => src.get(*x, *y).unwrap_or_default(),
+                None => [0.0; 4],
+            };
+        }
+    }
+}
+
+/// Pixels in a `width` x `height` wire, if it's no larger than
+/// `MAX_WIRE_PIXELS`.
+fn wire_pixels(width: u32, height: u32) -> Result<usize, String> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .filter(|pixels| *pixels <= MAX_WIRE_PIXELS)
+        .ok_or_else(|| format!("a {width}x{height} wire is larger than {MAX_WIRE_PIXELS} pixels"))
+}
+
+fn serpentine(panel_width: u32, panel_height: u32, columns: u32, rows: u32) -> Vec<PanelPlacement> {
+    let mut panels = vec![];
+    for row in 0..rows {
+        for i in 0..columns {
+            let (column, rotation) = if row % 2 == 0 {
+                (i, Rotation::R0)
+            } else {
+                (columns - 1 - i, Rotation::R180)
+            };
+            panels.push(PanelPlacement {
+                // Saturated placements are out of range, which `from_panels`
+                // rejects.
+                x: column.saturating_mul(panel_width),
+                y: row.saturating_mul(panel_height),
+                rotation,
+            });
+        }
+    }
+    panels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wire(config: &LayoutConfig, src: &Canvas) -> Vec<Vec<i32>> {
+        let mut wire = Canvas::new(1, 1);
+        PixelMap::from_config(config).unwrap().apply(src, &mut wire);
+        wire.indices()
+    }
+
+    fn panel(x: u32, y: u32, rotation: Rotation) -> PanelPlacement {
+        PanelPlacement { x, y, rotation }
+    }
+
+    /// Writes `contents` to a file of its own and parses it as a lookup table.
+    fn lut(name: &str, contents: &str) -> Result<PixelMap, String> {
+        let path =
+            std::env::temp_dir().join(format!("matryx-layout-{}-{name}.txt", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        let map = PixelMap::from_lut(path.to_str().unwrap());
+        fs::remove_file(&path).ok();
+        map
+    }
+
+    #[test]
+    fn panels_are_chained_in_order() {
+        // Two 2x2 panels stacked, the bottom one first in the chain.
+        let config = LayoutConfig::Panels {
+            panel_width: 2,
+            panel_height: 2,
+            panels: vec![panel(0, 2, Rotation::R0), panel(0, 0, Rotation::R0)],
+        };
+        assert_eq!(
+            wire(&config, &Canvas::numbered(2, 4)),
+            [[4, 5, 0, 1], [6, 7, 2, 3]]
+        );
+    }
+
+    #[test]
+    fn panel_rotations() {
+        let rotated = |rotation| LayoutConfig::Panels {
+            panel_width: 2,
+            panel_height: 2,
+            panels: vec![panel(0, 0, rotation)],
+        };
+        let src = Canvas::numbered(2, 2);
+        assert_eq!(wire(&rotated(Rotation::R0), &src), [[0, 1], [2, 3]]);
+        assert_eq!(wire(&rotated(Rotation::R90), &src), [[1, 3], [0, 2]]);
+        assert_eq!(wire(&rotated(Rotation::R180), &src), [[3, 2], [1, 0]]);
+        assert_eq!(wire(&rotated(Rotation::R270), &src), [[2, 0], [3, 1]]);
+    }
+
+    #[test]
+    fn serpentine_turns_every_other_row() {
+        let config = LayoutConfig::Serpentine {
+            panel_width: 2,
+            panel_height: 1,
+            columns: 2,
+            rows: 2,
+        };
+        let map = PixelMap::from_config(&config).unwrap();
+        assert_eq!(map.logical_size(), (4, 2));
+        assert_eq!(
+            wire(&config, &Canvas::numbered(4, 2)),
+            [[0, 1, 2, 3, 7, 6, 5, 4]]
+        );
+    }
+
+    #[test]
+    fn pixels_outside_the_canvas_are_black() {
+        let config = LayoutConfig::Panels {
+            panel_width: 2,
+            panel_height: 1,
+            panels: vec![panel(1, 0, Rotation::R0)],
+        };
+        let mut wire = Canvas::new(1, 1);
+        PixelMap::from_config(&config)
+            .unwrap()
+            .apply(&Canvas::numbered(2, 1), &mut wire);
+        assert_eq!(wire.pixels, [[1.0, 0.0, 0.0, 1.0], [0.0; 4]]);
+    }
+
+    #[test]
+    fn oversized_layouts_are_errors() {
+        let wide = LayoutConfig::Panels {
+            panel_width: u32::MAX,
+            panel_height: 1,
+            panels: vec![panel(0, 0, Rotation::R0); 2],
+        };
+        assert!(PixelMap::from_config(&wide).is_err());
+        let offset = LayoutConfig::Panels {
+            panel_width: 2,
+            panel_height: 1,
+            panels: vec![panel(u32::MAX, 0, Rotation::R0)],
+        };
+        assert!(PixelMap::from_config(&offset).is_err());
+        let tall = LayoutConfig::Serpentine {
+            panel_width: 1,
+            panel_height: 1 << 20,
+            columns: 1,
+            rows: 5000,
+        };
+        assert!(PixelMap::from_config(&tall).is_err());
+        let many = LayoutConfig::Serpentine {
+            panel_width: 64,
+            panel_height: 32,
+            columns: 1 << 16,
+            rows: 1 << 16,
+        };
+        assert!(PixelMap::from_config(&many).is_err());
+    }
+
+    #[test]
+    fn lookup_tables() {
+        let map = lut("valid", "# two pixels\n2 1\n1 0\n-\n").unwrap();
+        assert_eq!(map.logical_size(), (2, 1));
+        let mut wire = Canvas::new(1, 1);
+        map.apply(&Canvas::numbered(2, 1), &mut wire);
+        assert_eq!(wire.pixels, [[1.0, 0.0, 0.0, 1.0], [0.0; 4]]);
+    }
+
+    #[test]
+    fn malformed_lookup_tables_are_errors() {
+        assert!(lut("empty", "# nothing\n").is_err());
+        assert!(lut("short", "2 1\n0 0\n").is_err());
+        assert!(lut("long", "1 1\n0 0\n0 0\n").is_err());
+        assert!(lut("three", "1 1\n0 0 0\n").is_err());
+        assert!(lut("word", "1 1\nx 0\n").is_err());
+        assert!(lut("negative", "1 1\n-1 0\n").is_err());
+        assert!(lut("huge", "100000 10000\n0 0\n").is_err());
+        assert!(lut("overflow", "4294967295 4294967295\n0 0\n").is_err());
+        assert!(PixelMap::from_lut("/nonexistent/layout.txt").is_err());
+    }
+
+    #[test]
+    fn tiles_copy_their_region() {
+        let tile = Tile {
+            x: 1,
+            y: 1,
+            width: 2,
+            height: 1,
+        };
+        assert!(tile.fits(3, 2));
+        assert!(!tile.fits(2, 2));
+        assert!(!tile.fits(3, 1));
+        let far = Tile {
+            x: u32::MAX,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        assert!(!far.fits(3, 2));
+        let tall = Tile {
+            x: 0,
+            y: 1,
+            width: 1,
+            height: u32::MAX,
+        };
+        assert!(!tall.fits(3, 2));
+        let mut dst = Canvas::new(1, 1);
+        tile.apply(&Canvas::numbered(3, 2), &mut dst);
+        assert_eq!(dst.indices(), [[4, 5]]);
+    }
+}
@@ -0,0 +1,23 @@
+pub mod calibration;
+pub mod camera_thread;
+pub mod canvas;
+pub mod color;
+pub mod config;
+pub mod control;
+pub mod dither;
+pub mod font;
+pub mod frame_tick;
+pub mod gradient;
+pub mod layout;
+pub mod lifecycle;
+pub mod metrics;
+pub mod noise;
+pub mod notification;
+pub mod output;
+pub mod resample;
+pub mod scene_runner;
+pub mod scenes;
+pub mod transform;
+
+pub use canvas::Canvas;
+pub use scenes::{Scene, UpdateRate};
@@ -0,0 +1,130 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread, time,
+};
+
+use embedded_graphics::{
+    geometry::Point,
+    pixelcolor::Rgb888,
+    prelude::*,
+    text::{Alignment, Text},
+};
+use image::imageops::{self, FilterType};
+use log2::*;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use u8g2_fonts::{fonts, U8g2TextStyle};
+
+use crate::Canvas;
+
+const SHUTDOWN_POLL: time::Duration = time::Duration::from_millis(100);
+
+/// Sets the returned flag on SIGINT or SIGTERM. A second signal while the
+/// flag is already set exits immediately, in case shutdown itself hangs.
+pub fn register_shutdown() -> Arc<AtomicBool> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    for signal in [SIGINT, SIGTERM] {
+        if let Err(e) =
+            signal_hook::flag::register_conditional_shutdown(signal, 1, shutdown.clone())
+                .and_then(|_| signal_hook::flag::register(signal, shutdown.clone()))
+        {
+            error!("Failed to register handler for signal {}: {}", signal, e);
+        }
+    }
+
+    shutdown
+}
+
+/// Sleeps for `duration`, waking early if `shutdown` is set. Returns `false`
+/// if it woke because of shutdown.
+pub fn sleep_unless_shutdown(shutdown: &AtomicBool, duration: time::Duration) -> bool {
+    let deadline = time::Instant::now() + duration;
+    loop {
+        if shutdown.load(Ordering::Relaxed) {
+            return false;
+        }
+        let now = time::Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep(SHUTDOWN_POLL.min(deadline - now));
+    }
+}
+
+/// Exits the process if `feed` isn't called for a while. A scene that never
+/// returns can't be stopped from the frame loop, so this leaves it to the
+/// service manager to restart everything instead of the panel freezing.
+pub struct Watchdog {
+    start: time::Instant,
+    // Milliseconds from `start` to the last `feed`.
+    fed: Arc<AtomicU64>,
+}
+
+impl Watchdog {
+    /// Starts watching from a background thread, which stops once `shutdown`
+    /// is set.
+    pub fn spawn(timeout: time::Duration, shutdown: Arc<AtomicBool>) -> Self {
+        let start = time::Instant::now();
+        let fed = Arc::new(AtomicU64::new(0));
+        let watched = fed.clone();
+        thread::spawn(move || {
+            while sleep_unless_shutdown(&shutdown, timeout / 4) {
+                let last_feed = time::Duration::from_millis(watched.load(Ordering::Relaxed));
+                let stalled = start.elapsed().saturating_sub(last_feed);
+                if stalled >= timeout {
+                    error!("Frame loop stalled for {:?}, exiting", stalled);
+                    std::process::exit(1);
+                }
+            }
+        });
+        Watchdog { start, fed }
+    }
+
+    pub fn feed(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.fed.store(elapsed, Ordering::Relaxed);
+    }
+}
+
+/// Loads an image from `path`, scaled to fill a `width` x `height` canvas.
+pub fn load_frame(path: &str, width: u32, height: u32) -> Option<Canvas> {
+    let img = match image::open(path) {
+        Ok(img) => img.into_rgba8(),
+        Err(e) => {
+            error!("Failed to load frame {}: {}", path, e);
+            return None;
+        }
+    };
+    let img = imageops::resize(&img, width, height, FilterType::Triangle);
+
+    Some(Canvas::from(&img))
+}
+
+/// Draws the name and version, centered on the canvas.
+pub fn draw_version(canvas: &mut Canvas) {
+    canvas.clear();
+
+    let style = U8g2TextStyle::new(fonts::u8g2_font_6x10_tf, Rgb888::new(255, 255, 255));
+    let center = canvas.width as i32 / 2;
+    let middle = canvas.height as i32 / 2;
+
+    Text::with_alignment(
+        "Matryx",
+        Point::new(center, middle - 2),
+        style.clone(),
+        Alignment::Center,
+    )
+    .draw(canvas)
+    .unwrap();
+    Text::with_alignment(
+        concat!("v", env!("CARGO_PKG_VERSION")),
+        Point::new(center, middle + 9),
+        style,
+        Alignment::Center,
+    )
+    .draw(canvas)
+    .unwrap();
+}
@@ -1,10 +1,17 @@
-mod camera_thread;
-mod canvas;
-mod scenes;
-mod frame_tick;
-
-use canvas::Canvas;
-use led_matrix_zmq::client::{MatrixClient, MatrixClientSettings};
+use matryx_generator::{
+    camera_thread, canvas,
+    config::Config,
+    control,
+    frame_tick::{FrameTimer, OverrunPolicy},
+    gradient,
+    lifecycle,
+    metrics::{self, Metrics},
+    notification::Overlay,
+    output::MatrixOutput,
+    scene_runner::SceneRunner,
+    scenes::{self, ClockScene, TestPatternScene},
+    Canvas,
+};
 
 use log2::*;
 use std::{
@@ -13,32 +20,61 @@ use std::{
         Arc,
     },
     thread::{self},
+    time,
 };
 
-use scenes::{ClockScene, WaveScene};
-
-trait Scene {
-    fn tick(&mut self, _canvas: &mut Canvas, _tick: &frame_tick::FrameTick) {}
-}
-
 const LOG_SIZE: u64 = 100 * 1024 * 1024;
 const LOG_ROTATE: usize = 2;
 
-const MATRIX_ADDRS: &str = "tcp://localhost:42024";
-const CANVAS_WIDTH: u32 = 64;
-const CANVAS_HEIGHT: u32 = 32;
+const MATRIX_FLUSH_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+const TARGET_FPS: f32 = 30.0;
+const OVERRUN_POLICY: OverrunPolicy = OverrunPolicy::Skip;
+
+const METRICS_ON: bool = true;
+const METRICS_ADDR: &str = "0.0.0.0:9898";
+
+// Scene parameters can be read and changed live. Local only by default.
+const CONTROL_ON: bool = true;
+const CONTROL_ADDR: &str = "127.0.0.1:9899";
 
 const CAMERA_ON: bool = true;
 const CAMERA_LIGHT_THRESHOLD: u8 = 24;
+const CAMERA_JOIN_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+const DAY_BRIGHTNESS: u8 = 100;
+const NIGHT_BRIGHTNESS: u8 = 1;
+
+// Shown on startup. Without an image, the name and version are drawn instead.
+const SPLASH_ON: bool = true;
+const SPLASH_IMAGE: Option<&str> = None;
+const SPLASH_DURATION: time::Duration = time::Duration::from_secs(2);
+
+// Shown after shutdown. Without an image, the panel is left black.
+const OFFLINE_IMAGE: Option<&str> = None;
+const OFFLINE_BRIGHTNESS: u8 = 10;
+const FADE_OUT_DURATION: time::Duration = time::Duration::from_secs(1);
+
+// Exits, for the service manager to restart, if a frame takes this long, as
+// when a scene is stuck in a loop.
+const WATCHDOG_ON: bool = true;
+const WATCHDOG_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+// Shows the calibration test pattern instead of the usual scenes.
+const TEST_PATTERN_ON: bool = false;
 
 const SHIFTER_START: f32 = -180.0;
 const SHIFTER_END: f32 = SHIFTER_START * -1.0;
 
-fn main() {
-    let client = MatrixClient::new(MatrixClientSettings {
-        addrs: vec![MATRIX_ADDRS.to_string()],
-    });
+fn fade_out(output: &mut MatrixOutput, brightness: u8, canvas: &Canvas) {
+    let steps = (FADE_OUT_DURATION.as_secs_f32() * TARGET_FPS) as u32;
+    for step in (0..steps).rev() {
+        output.send((brightness as u32 * step / steps) as u8, canvas);
+        thread::sleep(FADE_OUT_DURATION / steps);
+    }
+}
 
+fn main() {
     #[cfg(debug_assertions)]
     let _log2 = log2::open("matryx-debug.txt")
         .size(LOG_SIZE)
@@ -56,47 +92,200 @@ fn main() {
         .start();
 
     warn!("Matryx V4");
-    let mut canvas_clock = Canvas::new(CANVAS_WIDTH, CANVAS_HEIGHT);
-    let mut canvas_wave = Canvas::new(CANVAS_WIDTH, CANVAS_HEIGHT);
-    let mut frame_timer = frame_tick::FrameTimer::new();
-    let mut scene = WaveScene::new(&canvas_wave, 1.0);
-    let mut clock_scene: ClockScene = ClockScene::new(&canvas_clock);
+    let config = Config::load().unwrap_or_else(|e| {
+        error!("Failed to load config: {}", e);
+        std::process::exit(1);
+    });
+    let shutdown = lifecycle::register_shutdown();
+    let mut canvas_clock = Canvas::new(config.width, config.height);
+    let mut canvas_night = Canvas::new(config.width, config.height);
+    let mut canvas_wave = Canvas::new(config.width, config.height);
+    let mut frame_timer = FrameTimer::new(TARGET_FPS, OVERRUN_POLICY);
+    let mut overlay = Overlay::new(config.width, config.height);
+    gradient::load_all(&config.palettes).unwrap_or_else(|e| {
+        error!("Failed to load palettes: {}", e);
+        std::process::exit(1);
+    });
+    let scene_failed = |e: String| -> SceneRunner {
+        error!("Failed to set up scene: {}", e);
+        std::process::exit(1);
+    };
+    let mut scene = SceneRunner::new(
+        &config.scene(&config.day_scene),
+        config.width,
+        config.height,
+        |width, height| scenes::build(&config.day_scene, &config, width, height),
+    )
+    .unwrap_or_else(scene_failed);
+    let mut clock_scene =
+        SceneRunner::new(&config.scene("clock"), config.width, config.height, |width, height| {
+            Ok(Box::new(ClockScene::new(width, height)))
+        })
+        .unwrap_or_else(scene_failed);
+    let mut test_pattern = TEST_PATTERN_ON.then(|| {
+        SceneRunner::new(&config.scene("test_pattern"), config.width, config.height, |width, height| {
+            Ok(Box::new(TestPatternScene::new(width, height)))
+        })
+        .unwrap_or_else(scene_failed)
+    });
     let camera_light_reading = Arc::new(AtomicU8::new(100));
     let camera_light_reading_clone = camera_light_reading.clone();
+    let metrics = Arc::new(Metrics::new());
+    let mut output = MatrixOutput::new(&config.outputs, config.width, config.height, metrics.clone())
+        .unwrap_or_else(|e| {
+            error!("Failed to set up outputs: {}", e);
+            std::process::exit(1);
+        });
+
+    if METRICS_ON {
+        metrics::serve(METRICS_ADDR, metrics.clone());
+    }
+    let control = CONTROL_ON.then(|| control::serve(CONTROL_ADDR)).flatten();
 
+    let mut camera_handle = None;
     if CAMERA_ON {
-        let mut handle_vec = vec![]; // JoinHandles will go in here
-        let handle = thread::spawn(move || camera_thread::cam_thread_loop(camera_light_reading_clone));
-        handle_vec.push(handle); // save the handle so we can call join on it outside of the loop
+        let camera_metrics = metrics.clone();
+        let camera_shutdown = shutdown.clone();
+        camera_handle = Some(thread::spawn(move || {
+            camera_thread::cam_thread_loop(
+                camera_light_reading_clone,
+                camera_metrics,
+                camera_shutdown,
+            )
+        }));
+    }
+
+    if SPLASH_ON {
+        let splash = SPLASH_IMAGE
+            .and_then(|path| lifecycle::load_frame(path, config.width, config.height))
+            .unwrap_or_else(|| {
+                let mut splash = Canvas::new(config.width, config.height);
+                lifecycle::draw_version(&mut splash);
+                splash
+            });
+        output.send(DAY_BRIGHTNESS, &splash);
+        lifecycle::sleep_unless_shutdown(&shutdown, SPLASH_DURATION);
     }
 
     let mut shifter: f32 = SHIFTER_START;
+    let mut night = false;
+    let watchdog =
+        WATCHDOG_ON.then(|| lifecycle::Watchdog::spawn(WATCHDOG_TIMEOUT, shutdown.clone()));
 
-    loop {
+    while !shutdown.load(Ordering::Relaxed) {
         let tick = frame_timer.tick();
-        clock_scene.tick(&mut canvas_clock, &tick);
+        if let Some(watchdog) = &watchdog {
+            watchdog.feed();
+        }
+        if let Some(control) = &control {
+            let mut runners = vec![&mut scene, &mut clock_scene];
+            runners.extend(test_pattern.as_mut());
+            control.handle_pending(&mut runners, &mut overlay);
+        }
+
+        if let Some(test_pattern) = &mut test_pattern {
+            let changed = match test_pattern.render(&tick) {
+                Some(_) => test_pattern.changed(),
+                None => true,
+            };
+            if changed || !output.repeat(DAY_BRIGHTNESS) {
+                if test_pattern.failed() {
+                    canvas_wave.clear();
+                } else {
+                    canvas_wave.clone_from(test_pattern.canvas());
+                }
+                output.send(DAY_BRIGHTNESS, &canvas_wave);
+            }
+            metrics.observe_frame(&frame_timer.wait_for_next_frame());
+            continue;
+        }
+
+        // The clock's canvas is only copied when it has changed.
+        let clock_changed = match metrics.time_scene("clock", || clock_scene.render(&tick)) {
+            Some(_) => clock_scene.changed(),
+            None => true,
+        };
+        if clock_changed {
+            if clock_scene.failed() {
+                canvas_clock.clear();
+            } else {
+                canvas_clock.clone_from(clock_scene.canvas());
+            }
+        }
         let light_reading = camera_light_reading.load(Ordering::Acquire);
         
         #[cfg(not(debug_assertions))]
         debug!("camera light reading: {0}", light_reading);
 
-        if light_reading <= CAMERA_LIGHT_THRESHOLD {
-            canvas::filter_quarter(&mut canvas_clock);
-            client.send_brightness(1);
-            client.send_frame(canvas_clock.pixels());
-        } else {
-            scene.tick(&mut canvas_wave, &tick);
+        let was_night = night;
+        night = light_reading <= CAMERA_LIGHT_THRESHOLD;
+        if night {
+            scene.exit();
+            // The night frame is only the clock and notifications, so while
+            // neither changes the servers keep showing the last one.
+            let redraw = clock_changed || !was_night || !overlay.idle();
+            if redraw {
+                canvas_night.clone_from(&canvas_clock);
+                metrics.time_filter("quarter", || canvas::filter_quarter(&mut canvas_night));
+            }
+            if redraw || !output.repeat(NIGHT_BRIGHTNESS) {
+                output.send(NIGHT_BRIGHTNESS, overlay.apply(&tick, &canvas_night));
+            }
+        } else if let Some(wave) =
+            metrics.time_scene(scene.scene().name(), || scene.render(&tick))
+        {
+            // The hue shift moves on every frame, so day frames always change
+            // and are rebuilt whether or not the scene updated.
+            canvas_wave.clone_from(wave);
             shifter = if shifter == SHIFTER_END {
                 SHIFTER_START
             } else {
                 shifter + 1.0
             };
-            client.send_brightness(100);
-            canvas::filter_hue_shift(&mut canvas_wave, shifter);
-            canvas::filter_bright_background(&mut canvas_wave, &mut canvas_clock, 0.1);
-            canvas::filter_rotate_right(&mut canvas_wave);
-            client.send_frame(canvas_wave.pixels());
+            metrics.time_filter("hue_shift", || {
+                canvas::filter_hue_shift(&mut canvas_wave, shifter)
+            });
+            metrics.time_filter("bright_background", || {
+                canvas::filter_bright_background(&mut canvas_wave, &mut canvas_clock, 0.1)
+            });
+            output.send(DAY_BRIGHTNESS, overlay.apply(&tick, &canvas_wave));
+        } else {
+            // The day scene failed, so the clock stands in until it restarts.
+            canvas_wave.clone_from(&canvas_clock);
+            output.send(DAY_BRIGHTNESS, overlay.apply(&tick, &canvas_wave));
+        }
+        let stats = frame_timer.wait_for_next_frame();
+        if stats.overran() {
+            debug!("frame {} late by {:?}, skipped {}", tick.index, stats.late, stats.skipped);
+        }
+        metrics.observe_frame(&stats);
+    }
+
+    warn!("Shutting down");
+    if night {
+        fade_out(&mut output, NIGHT_BRIGHTNESS, &canvas_night);
+    } else {
+        fade_out(&mut output, DAY_BRIGHTNESS, &canvas_wave);
+    }
+    // Frames are dropped while a server is busy, so make room for the last one.
+    output.flush(MATRIX_FLUSH_TIMEOUT);
+
+    match OFFLINE_IMAGE.and_then(|path| lifecycle::load_frame(path, config.width, config.height)) {
+        Some(offline) => output.send(OFFLINE_BRIGHTNESS, &offline),
+        None => output.send(0, &Canvas::new(config.width, config.height)),
+    }
+    output.flush(MATRIX_FLUSH_TIMEOUT);
+
+    if let Some(handle) = camera_handle {
+        // The camera can block inside the driver, so don't wait on it forever.
+        let deadline = time::Instant::now() + CAMERA_JOIN_TIMEOUT;
+        while !handle.is_finished() && time::Instant::now() < deadline {
+            thread::sleep(time::Duration::from_millis(50));
+        }
+        if handle.is_finished() {
+            let _ = handle.join();
+        } else {
+            warn!("Camera thread did not stop in time");
         }
-        frame_timer.wait_for_next_frame();
     }
 }
\ No newline at end of file
@@ -0,0 +1,432 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    thread, time,
+};
+
+use log2::*;
+
+use crate::frame_tick::FrameStats;
+
+// Upper bounds in seconds, chosen around the 33ms budget of a 30fps frame.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.015, 0.02, 0.033, 0.05, 0.1, 0.25,
+];
+
+#[derive(Default)]
+struct MatrixLink {
+    up: bool,
+    dropped_frames: u64,
+    unchanged_frames: u64,
+    reconnects: u64,
+}
+
+#[derive(Clone)]
+struct Histogram {
+    counts: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            counts: [0; DURATION_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in DURATION_BUCKETS.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (bound, count) in DURATION_BUCKETS.iter().zip(self.counts.iter()) {
+            let _ = writeln!(out, "{name}_bucket{{{labels}{sep}le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {}",
+            self.count
+        );
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{labels} {}", self.count);
+    }
+}
+
+pub struct Metrics {
+    scene_render: Mutex<BTreeMap<&'static str, Histogram>>,
+    filter_render: Mutex<BTreeMap<&'static str, Histogram>>,
+    frame: Mutex<Histogram>,
+    send: Mutex<Histogram>,
+    missed_frames: AtomicU64,
+    skipped_frames: AtomicU64,
+    camera_reading: AtomicU8,
+    camera_last_reading: Mutex<Option<time::Instant>>,
+    camera_restarts: AtomicU64,
+    brightness: AtomicU8,
+    matrix_links: Mutex<BTreeMap<String, MatrixLink>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            scene_render: Mutex::new(BTreeMap::new()),
+            filter_render: Mutex::new(BTreeMap::new()),
+            frame: Mutex::new(Histogram::new()),
+            send: Mutex::new(Histogram::new()),
+            missed_frames: AtomicU64::new(0),
+            skipped_frames: AtomicU64::new(0),
+            camera_reading: AtomicU8::new(0),
+            camera_last_reading: Mutex::new(None),
+            camera_restarts: AtomicU64::new(0),
+            brightness: AtomicU8::new(0),
+            matrix_links: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn time_scene<T>(&self, scene: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = time::Instant::now();
+        let ret = f();
+        observe_labeled(&self.scene_render, scene, start.elapsed());
+        ret
+    }
+
+    pub fn time_filter<T>(&self, filter: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = time::Instant::now();
+        let ret = f();
+        observe_labeled(&self.filter_render, filter, start.elapsed());
+        ret
+    }
+
+    pub fn time_send<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = time::Instant::now();
+        let ret = f();
+        self.send
+            .lock()
+            .unwrap()
+            .observe(start.elapsed().as_secs_f64());
+        ret
+    }
+
+    pub fn observe_frame(&self, stats: &FrameStats) {
+        self.frame.lock().unwrap().observe(stats.busy.as_secs_f64());
+        if stats.overran() {
+            self.missed_frames.fetch_add(1, Ordering::Relaxed);
+        }
+        self.skipped_frames
+            .fetch_add(stats.skipped, Ordering::Relaxed);
+    }
+
+    pub fn set_camera_reading(&self, reading: u8) {
+        self.camera_reading.store(reading, Ordering::Relaxed);
+        *self.camera_last_reading.lock().unwrap() = Some(time::Instant::now());
+    }
+
+    pub fn inc_camera_restarts(&self) {
+        self.camera_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_brightness(&self, brightness: u8) {
+        self.brightness.store(brightness, Ordering::Relaxed);
+    }
+
+    fn update_matrix_link(&self, addr: &str, f: impl FnOnce(&mut MatrixLink)) {
+        let mut links = self.matrix_links.lock().unwrap();
+        f(links.entry(addr.to_string()).or_default());
+    }
+
+    pub fn set_matrix_up(&self, addr: &str, up: bool) {
+        self.update_matrix_link(addr, |link| link.up = up);
+    }
+
+    pub fn inc_matrix_dropped_frames(&self, addr: &str) {
+        self.update_matrix_link(addr, |link| link.dropped_frames += 1);
+    }
+
+    pub fn inc_matrix_unchanged_frames(&self, addr: &str) {
+        self.update_matrix_link(addr, |link| link.unchanged_frames += 1);
+    }
+
+    pub fn inc_matrix_reconnects(&self, addr: &str) {
+        self.update_matrix_link(addr, |link| link.reconnects += 1);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        write_header(
+            &mut out,
+            "matryx_scene_render_seconds",
+            "histogram",
+            "Time spent in Scene::tick.",
+        );
+        for (scene, histogram) in self.scene_render.lock().unwrap().iter() {
+            histogram.write(
+                &mut out,
+                "matryx_scene_render_seconds",
+                &format!("scene=\"{scene}\""),
+            );
+        }
+
+        write_header(
+            &mut out,
+            "matryx_filter_render_seconds",
+            "histogram",
+            "Time spent in each canvas filter.",
+        );
+        for (filter, histogram) in self.filter_render.lock().unwrap().iter() {
+            histogram.write(
+                &mut out,
+                "matryx_filter_render_seconds",
+                &format!("filter=\"{filter}\""),
+            );
+        }
+
+        write_header(
+            &mut out,
+            "matryx_frame_seconds",
+            "histogram",
+            "Time from frame tick to the end of the send, before sleeping.",
+        );
+        self.frame
+            .lock()
+            .unwrap()
+            .write(&mut out, "matryx_frame_seconds", "");
+
+        write_header(
+            &mut out,
+            "matryx_send_seconds",
+            "histogram",
+            "Latency of sending a frame to the matrix.",
+        );
+        self.send
+            .lock()
+            .unwrap()
+            .write(&mut out, "matryx_send_seconds", "");
+
+        write_header(
+            &mut out,
+            "matryx_missed_frames_total",
+            "counter",
+            "Frames that finished after the next frame's deadline.",
+        );
+        let _ = writeln!(
+            out,
+            "matryx_missed_frames_total {}",
+            self.missed_frames.load(Ordering::Relaxed)
+        );
+
+        write_header(
+            &mut out,
+            "matryx_skipped_frames_total",
+            "counter",
+            "Frames dropped by the overrun policy.",
+        );
+        let _ = writeln!(
+            out,
+            "matryx_skipped_frames_total {}",
+            self.skipped_frames.load(Ordering::Relaxed)
+        );
+
+        write_header(
+            &mut out,
+            "matryx_camera_light_reading",
+            "gauge",
+            "Last 90th percentile luma read from the camera.",
+        );
+        let _ = writeln!(
+            out,
+            "matryx_camera_light_reading {}",
+            self.camera_reading.load(Ordering::Relaxed)
+        );
+
+        write_header(
+            &mut out,
+            "matryx_camera_reading_age_seconds",
+            "gauge",
+            "Seconds since the camera last produced a reading.",
+        );
+        let age = match *self.camera_last_reading.lock().unwrap() {
+            Some(instant) => instant.elapsed().as_secs_f64(),
+            None => f64::INFINITY,
+        };
+        let _ = writeln!(out, "matryx_camera_reading_age_seconds {}", fmt_float(age));
+
+        write_header(
+            &mut out,
+            "matryx_camera_restarts_total",
+            "counter",
+            "Times the camera thread restarted after an error.",
+        );
+        let _ = writeln!(
+            out,
+            "matryx_camera_restarts_total {}",
+            self.camera_restarts.load(Ordering::Relaxed)
+        );
+
+        write_header(
+            &mut out,
+            "matryx_brightness",
+            "gauge",
+            "Brightness last sent to the matrix.",
+        );
+        let _ = writeln!(
+            out,
+            "matryx_brightness {}",
+            self.brightness.load(Ordering::Relaxed)
+        );
+
+        let links = self.matrix_links.lock().unwrap();
+        write_header(
+            &mut out,
+            "matryx_matrix_up",
+            "gauge",
+            "Whether the last sends to a matrix server succeeded.",
+        );
+        for (addr, link) in links.iter() {
+            let _ = writeln!(out, "matryx_matrix_up{{addr=\"{addr}\"}} {}", link.up as u8);
+        }
+
+        write_header(
+            &mut out,
+            "matryx_matrix_dropped_frames_total",
+            "counter",
+            "Frames dropped because the matrix server was still busy with the last one.",
+        );
+        for (addr, link) in links.iter() {
+            let _ = writeln!(
+                out,
+                "matryx_matrix_dropped_frames_total{{addr=\"{addr}\"}} {}",
+                link.dropped_frames
+            );
+        }
+
+        write_header(
+            &mut out,
+            "matryx_matrix_unchanged_frames_total",
+            "counter",
+            "Frames not sent because they matched the last frame sent.",
+        );
+        for (addr, link) in links.iter() {
+            let _ = writeln!(
+                out,
+                "matryx_matrix_unchanged_frames_total{{addr=\"{addr}\"}} {}",
+                link.unchanged_frames
+            );
+        }
+
+        write_header(
+            &mut out,
+            "matryx_matrix_reconnects_total",
+            "counter",
+            "Reconnect attempts to a matrix server.",
+        );
+        for (addr, link) in links.iter() {
+            let _ = writeln!(
+                out,
+                "matryx_matrix_reconnects_total{{addr=\"{addr}\"}} {}",
+                link.reconnects
+            );
+        }
+        drop(links);
+
+        out
+    }
+}
+
+fn observe_labeled(
+    histograms: &Mutex<BTreeMap<&'static str, Histogram>>,
+    label: &'static str,
+    duration: time::Duration,
+) {
+    histograms
+        .lock()
+        .unwrap()
+        .entry(label)
+        .or_insert_with(Histogram::new)
+        .observe(duration.as_secs_f64());
+}
+
+fn write_header(out: &mut String, name: &str, type_: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {type_}");
+}
+
+fn fmt_float(value: f64) -> String {
+    if value.is_infinite() {
+        "+Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Serves `GET /metrics` on `addr` from a background thread.
+pub fn serve(addr: &str, metrics: Arc<Metrics>) {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind metrics endpoint {}: {}", addr, e);
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_connection(stream, &metrics) {
+                        warn!("Metrics request failed: {}", e);
+                    }
+                }
+                Err(e) => warn!("Metrics connection failed: {}", e),
+            }
+        }
+    });
+}
+
+fn handle_connection(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(time::Duration::from_secs(1)))?;
+
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", metrics.render())
+    } else {
+        ("404 Not Found", String::new())
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
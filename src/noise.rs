@@ -0,0 +1,118 @@
+// Skewing factors between the simplex grid and the square grid.
+const F2: f32 = 0.36602542; // (sqrt(3) - 1) / 2
+const G2: f32 = 0.21132487; // (3 - sqrt(3)) / 6
+const F3: f32 = 1.0 / 3.0;
+const G3: f32 = 1.0 / 6.0;
+
+// Midpoints of a cube's edges, as in Ken Perlin's improved noise.
+const GRADIENTS: [[f32; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+/// Picks a gradient for a lattice point. A hash instead of the usual
+/// permutation table, so the noise doesn't repeat every 256 units.
+fn gradient(i: i32, j: i32, k: i32) -> [f32; 3] {
+    let mut h = (i as u32).wrapping_mul(0x8da6_b343)
+        ^ (j as u32).wrapping_mul(0xd816_3841)
+        ^ (k as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    GRADIENTS[(h % 12) as usize]
+}
+
+/// 2D simplex noise, in `-1.0..=1.0`, after Stefan Gustavson's reference
+/// implementation.
+pub fn simplex2(x: f32, y: f32) -> f32 {
+    let s = (x + y) * F2;
+    let i = (x + s).floor();
+    let j = (y + s).floor();
+    let t = (i + j) * G2;
+    let x0 = x - (i - t);
+    let y0 = y - (j - t);
+
+    // Which of the two triangles of the skewed cell the point is in.
+    let (i1, j1) = if x0 > y0 { (1.0, 0.0) } else { (0.0, 1.0) };
+    let corners = [
+        (0.0, 0.0, x0, y0),
+        (i1, j1, x0 - i1 + G2, y0 - j1 + G2),
+        (1.0, 1.0, x0 - 1.0 + 2.0 * G2, y0 - 1.0 + 2.0 * G2),
+    ];
+
+    let mut n = 0.0;
+    for (di, dj, dx, dy) in corners {
+        let t = 0.5 - dx * dx - dy * dy;
+        if t > 0.0 {
+            let g = gradient((i + di) as i32, (j + dj) as i32, 0);
+            n += t * t * t * t * (g[0] * dx + g[1] * dy);
+        }
+    }
+    70.0 * n
+}
+
+/// 3D simplex noise, in `-1.0..=1.0`. Sweeping `z` with time animates 2D noise
+/// without it sliding sideways.
+pub fn simplex3(x: f32, y: f32, z: f32) -> f32 {
+    let s = (x + y + z) * F3;
+    let i = (x + s).floor();
+    let j = (y + s).floor();
+    let k = (z + s).floor();
+    let t = (i + j + k) * G3;
+    let x0 = x - (i - t);
+    let y0 = y - (j - t);
+    let z0 = z - (k - t);
+
+    // Which of the six tetrahedra of the skewed cube the point is in.
+    let ([i1, j1, k1], [i2, j2, k2]) = if x0 >= y0 {
+        if y0 >= z0 {
+            ([1.0, 0.0, 0.0], [1.0, 1.0, 0.0])
+        } else if x0 >= z0 {
+            ([1.0, 0.0, 0.0], [1.0, 0.0, 1.0])
+        } else {
+            ([0.0, 0.0, 1.0], [1.0, 0.0, 1.0])
+        }
+    } else if y0 < z0 {
+        ([0.0, 0.0, 1.0], [0.0, 1.0, 1.0])
+    } else if x0 < z0 {
+        ([0.0, 1.0, 0.0], [0.0, 1.0, 1.0])
+    } else {
+        ([0.0, 1.0, 0.0], [1.0, 1.0, 0.0])
+    };
+    let corners = [
+        ([0.0, 0.0, 0.0], [x0, y0, z0]),
+        ([i1, j1, k1], [x0 - i1 + G3, y0 - j1 + G3, z0 - k1 + G3]),
+        (
+            [i2, j2, k2],
+            [x0 - i2 + 2.0 * G3, y0 - j2 + 2.0 * G3, z0 - k2 + 2.0 * G3],
+        ),
+        (
+            [1.0, 1.0, 1.0],
+            [
+                x0 - 1.0 + 3.0 * G3,
+                y0 - 1.0 + 3.0 * G3,
+                z0 - 1.0 + 3.0 * G3,
+            ],
+        ),
+    ];
+
+    let mut n = 0.0;
+    for ([di, dj, dk], [dx, dy, dz]) in corners {
+        let t = 0.6 - dx * dx - dy * dy - dz * dz;
+        if t > 0.0 {
+            let g = gradient((i + di) as i32, (j + dj) as i32, (k + dk) as i32);
+            n += t * t * t * t * (g[0] * dx + g[1] * dy + g[2] * dz);
+        }
+    }
+    32.0 * n
+}
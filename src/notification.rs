@@ -0,0 +1,545 @@
+use std::cmp::Reverse;
+
+use embedded_graphics::{pixelcolor::Rgb888, prelude::*};
+use log2::*;
+use serde::{Deserialize, Serialize};
+use u8g2_fonts::{
+    types::{FontColor, VerticalPosition},
+    FontRenderer,
+};
+
+use crate::{canvas::srgb_to_linear, font, frame_tick::FrameTick, scenes::param, Canvas};
+
+// Notifications waiting behind the one shown, past which `push` refuses more.
+const MAX_WAITING: usize = 16;
+const MAX_TEXT_LEN: usize = 256;
+const MAX_DURATION: f32 = 300.0;
+const DEFAULT_FONT: &str = "6x10_tf";
+
+// How long sliding or fading in, and out again, take in seconds.
+const ANIMATION_DURATION: f32 = 0.3;
+// A flashing notification flashes this many times as it appears, once a
+// period, lit for the first half of it.
+const FLASHES: u32 = 3;
+const FLASH_PERIOD: f32 = 0.3;
+const FLASH_ALPHA: f32 = 0.8;
+// How much of the scene a banner hides behind it.
+const BANNER_ALPHA: f32 = 0.85;
+// Text too wide for the panel scrolls, in pixels per second.
+const SCROLL_SPEED: f32 = 24.0;
+const SCROLL_GAP: i32 = 16;
+
+// 8x8 icons, a row per byte with the leftmost pixel in the high bit.
+const ICONS: &[(&str, [u8; 8])] = &[
+    ("bell", [0x18, 0x3c, 0x7e, 0x7e, 0x7e, 0xff, 0x00, 0x18]),
+    ("check", [0x00, 0x01, 0x03, 0x86, 0xcc, 0x78, 0x30, 0x00]),
+    ("cross", [0xc3, 0x66, 0x3c, 0x18, 0x3c, 0x66, 0xc3, 0x00]),
+    ("heart", [0x66, 0xff, 0xff, 0xff, 0x7e, 0x3c, 0x18, 0x00]),
+    ("info", [0x18, 0x18, 0x00, 0x38, 0x18, 0x18, 0x18, 0x3c]),
+    ("mail", [0xff, 0xc3, 0xa5, 0x99, 0x81, 0x81, 0xff, 0x00]),
+    ("warning", [0x18, 0x24, 0x24, 0x5a, 0x5a, 0x81, 0x99, 0xff]),
+];
+
+/// Notifications of higher priority are shown first, and cut short one of
+/// lower priority that is showing, which waits to be shown again.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Style {
+    /// A strip along the top of the panel, over the scene.
+    #[default]
+    Banner,
+    /// The whole panel, hiding the scene.
+    Takeover,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Animation {
+    /// Slides down from the top, and back up.
+    #[default]
+    Slide,
+    Fade,
+    None,
+}
+
+/// A short alert drawn over whatever is on the panel, posted to the control
+/// endpoint.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Notification {
+    pub text: String,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Seconds it's shown for, not counting the animations.
+    #[serde(default = "default_duration")]
+    pub duration: f32,
+    /// The name of an icon drawn before the text, like `bell` or `warning`.
+    pub icon: Option<String>,
+    #[serde(default)]
+    pub style: Style,
+    #[serde(default)]
+    pub animation: Animation,
+    /// Flashes the whole panel in the notification's color as it appears.
+    #[serde(default)]
+    pub flash: bool,
+    /// sRGB, each channel in `0.0..=1.0`. By default it follows the priority.
+    pub color: Option<[f32; 3]>,
+    pub font: Option<String>,
+    /// Notifications with the same key, or the same text when they have none,
+    /// are merged rather than shown twice.
+    pub key: Option<String>,
+}
+
+fn default_duration() -> f32 {
+    5.0
+}
+
+impl Notification {
+    pub fn validate(&self) -> Result<(), String> {
+        param::validate_text(
+            "notification",
+            &self.text,
+            MAX_TEXT_LEN,
+            self.color,
+            self.font.as_deref(),
+        )?;
+        if !(self.duration > 0.0 && self.duration <= MAX_DURATION) {
+            return Err(format!(
+                "duration must be over 0 and at most {MAX_DURATION}"
+            ));
+        }
+        if let Some(icon) = &self.icon {
+            if icon_named(icon).is_none() {
+                return Err(format!(
+                    "unknown icon {icon}, expected one of {}",
+                    icon_names()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn key(&self) -> &str {
+        self.key.as_deref().unwrap_or(&self.text)
+    }
+
+    fn color(&self) -> [f32; 3] {
+        self.color.unwrap_or(match self.priority {
+            Priority::Low => [0.6, 0.6, 0.6],
+            Priority::Normal => [1.0, 1.0, 1.0],
+            Priority::High => [1.0, 0.6, 0.0],
+            Priority::Urgent => [1.0, 0.1, 0.1],
+        })
+    }
+}
+
+fn icon_named(name: &str) -> Option<&'static [u8; 8]> {
+    ICONS
+        .iter()
+        .find(|(icon, _)| *icon == name)
+        .map(|(_, bits)| bits)
+}
+
+fn icon_names() -> String {
+    ICONS
+        .iter()
+        .map(|(icon, _)| *icon)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// What `Overlay::push` did with a notification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Queued,
+    /// It matched one waiting or showing, which was extended instead.
+    Merged,
+}
+
+/// The notification being shown.
+struct Showing {
+    notification: Notification,
+    font: FontRenderer,
+    text: String,
+    text_width: i32,
+    start: f32,
+    // When it starts leaving.
+    end: f32,
+}
+
+impl Showing {
+    fn new(notification: Notification, start: f32) -> Self {
+        let font = font::checked(notification.font.as_deref(), DEFAULT_FONT);
+        let (text, text_width) = font::line(&font, &notification.text);
+        let end = start + animation_duration(&notification) + notification.duration;
+
+        Showing {
+            notification,
+            font,
+            text,
+            text_width,
+            start,
+            end,
+        }
+    }
+
+    fn leaving(&self, t: f32) -> bool {
+        t >= self.end
+    }
+
+    fn done(&self, t: f32) -> bool {
+        t >= self.end + animation_duration(&self.notification)
+    }
+
+    /// How far in it is, from 0 before it enters to 1 once it has.
+    fn visibility(&self, t: f32) -> f32 {
+        if self.notification.animation == Animation::None {
+            return if self.leaving(t) { 0.0 } else { 1.0 };
+        }
+        let entering = (t - self.start) / ANIMATION_DURATION;
+        let leaving = 1.0 - (t - self.end) / ANIMATION_DURATION;
+        entering.min(leaving).clamp(0.0, 1.0)
+    }
+
+    fn flash(&self, frame: &mut Canvas, t: f32) {
+        let age = t - self.start;
+        if !self.notification.flash
+            || age >= FLASHES as f32 * FLASH_PERIOD
+            || age % FLASH_PERIOD >= FLASH_PERIOD / 2.0
+        {
+            return;
+        }
+        let [r, g, b] = self.notification.color().map(srgb_to_linear);
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                frame.blend_pixel(x, y, [r, g, b, FLASH_ALPHA]);
+            }
+        }
+    }
+
+    fn draw(&self, layer: &mut Canvas, t: f32) {
+        let (width, height) = (layer.width as i32, layer.height as i32);
+        let line_height = (self.font.get_ascent() - self.font.get_descent()) as i32;
+        let (area_height, background) = match self.notification.style {
+            Style::Banner => ((line_height + 2).min(height), [0.0, 0.0, 0.0, BANNER_ALPHA]),
+            Style::Takeover => (height, [0.0, 0.0, 0.0, 1.0]),
+        };
+        let top = match self.notification.animation {
+            Animation::Slide => -((1.0 - self.visibility(t)) * area_height as f32).round() as i32,
+            _ => 0,
+        };
+        fill(layer, 0, top, width, area_height, background);
+
+        let color = self.notification.color();
+        let icon = self.notification.icon.as_deref().and_then(icon_named);
+        // Where the icon goes, and the space left for the text.
+        let (icon_at, text_left, text_y) = match (self.notification.style, icon) {
+            (_, None) => (None, 0, top + area_height / 2),
+            (Style::Banner, Some(_)) => {
+                let scale = ((area_height - 2) / 8).max(1);
+                let at = Point::new(1, top + (area_height - 8 * scale) / 2);
+                (Some((at, scale)), 8 * scale + 3, top + area_height / 2)
+            }
+            (Style::Takeover, Some(_)) => {
+                let scale = ((area_height / 2 - 2) / 8).max(1);
+                let at = Point::new((width - 8 * scale) / 2, top + area_height / 4 - 4 * scale);
+                (Some((at, scale)), 0, top + area_height * 3 / 4)
+            }
+        };
+
+        let text_space = width - text_left;
+        if self.text_width <= text_space {
+            self.draw_text(
+                layer,
+                text_left + (text_space - self.text_width) / 2,
+                text_y,
+                color,
+            );
+        } else {
+            let shift = ((t - self.start) * SCROLL_SPEED) as i32 % (self.text_width + SCROLL_GAP);
+            let x = text_left - shift;
+            self.draw_text(layer, x, text_y, color);
+            self.draw_text(layer, x + self.text_width + SCROLL_GAP, text_y, color);
+        }
+
+        if let (Some((at, scale)), Some(bits)) = (icon_at, icon) {
+            // Scrolling text passes under the icon.
+            fill(layer, 0, top, text_left, area_height, background);
+            let [r, g, b] = color.map(srgb_to_linear);
+            for (row, bits) in bits.iter().enumerate() {
+                for column in 0..8 {
+                    if bits & (0x80 >> column) != 0 {
+                        let x = at.x + column * scale;
+                        let y = at.y + row as i32 * scale;
+                        fill(layer, x, y, scale, scale, [r, g, b, 1.0]);
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_text(&self, layer: &mut Canvas, x: i32, y: i32, color: [f32; 3]) {
+        let [r, g, b] = color.map(|c| (c * 255.0).round() as u8);
+        self.font
+            .render(
+                self.text.as_str(),
+                Point::new(x, y),
+                VerticalPosition::Center,
+                FontColor::Transparent(Rgb888::new(r, g, b)),
+                layer,
+            )
+            .ok();
+    }
+}
+
+fn animation_duration(notification: &Notification) -> f32 {
+    match notification.animation {
+        Animation::None => 0.0,
+        _ => ANIMATION_DURATION,
+    }
+}
+
+/// Sets the pixels of a rectangle, clipped to the canvas.
+fn fill(canvas: &mut Canvas, x: i32, y: i32, width: i32, height: i32, rgba: [f32; 4]) {
+    for y in y.max(0)..(y + height).min(canvas.height as i32) {
+        for x in x.max(0)..(x + width).min(canvas.width as i32) {
+            canvas.set(x as u32, y as u32, rgba);
+        }
+    }
+}
+
+/// Queued notifications, drawn one at a time over the finished frame, after
+/// the scenes and filters, so they show over whatever is running.
+pub struct Overlay {
+    // In the order they'll be shown, by priority and then arrival.
+    waiting: Vec<Notification>,
+    showing: Option<Showing>,
+    // The time of the last frame, for notifications arriving between frames.
+    t: f32,
+    layer: Canvas,
+    frame: Canvas,
+    // Whether the last `apply` drew a notification.
+    drawn: bool,
+}
+
+impl Overlay {
+    pub fn new(width: u32, height: u32) -> Self {
+        Overlay {
+            waiting: Vec::new(),
+            showing: None,
+            t: 0.0,
+            layer: Canvas::new(width, height),
+            frame: Canvas::new(width, height),
+            drawn: false,
+        }
+    }
+
+    /// Queues `notification`, or merges it into a waiting or showing one with
+    /// the same key, which takes the higher of their priorities and shows for
+    /// at least the new duration.
+    pub fn push(&mut self, notification: Notification) -> Result<Outcome, String> {
+        notification.validate()?;
+        let t = self.t;
+
+        if let Some(showing) = &mut self.showing {
+            if showing.notification.key() == notification.key() && !showing.leaving(t) {
+                showing.end = showing.end.max(t + notification.duration);
+                showing.notification.priority =
+                    showing.notification.priority.max(notification.priority);
+                return Ok(Outcome::Merged);
+            }
+        }
+        if let Some(waiting) = self
+            .waiting
+            .iter_mut()
+            .find(|waiting| waiting.key() == notification.key())
+        {
+            waiting.duration = waiting.duration.max(notification.duration);
+            waiting.priority = waiting.priority.max(notification.priority);
+            self.sort();
+            return Ok(Outcome::Merged);
+        }
+        // A lower priority one showing is cut short and waits again, so it
+        // needs room too.
+        let preempts = self.showing.as_ref().is_some_and(|showing| {
+            showing.notification.priority < notification.priority && !showing.leaving(t)
+        });
+        if self.waiting.len() + usize::from(preempts) + 1 > MAX_WAITING {
+            return Err(format!("{MAX_WAITING} notifications already waiting"));
+        }
+
+        info!("Notification: {}", notification.text);
+        if let Some(showing) = self.showing.as_mut().filter(|_| preempts) {
+            // Ahead of the others of its priority, as it was shown first.
+            showing.end = t;
+            self.waiting.insert(0, showing.notification.clone());
+        }
+        self.waiting.push(notification);
+        self.sort();
+        Ok(Outcome::Queued)
+    }
+
+    /// Sends the notification being shown on its way out. Returns whether
+    /// there was one.
+    pub fn dismiss(&mut self) -> bool {
+        match &mut self.showing {
+            Some(showing) if !showing.leaving(self.t) => {
+                showing.end = self.t;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `apply` would return its frame as is, like it did last time,
+    /// with nothing showing or waiting.
+    pub fn idle(&self) -> bool {
+        !self.drawn && self.showing.is_none() && self.waiting.is_empty()
+    }
+
+    /// How many notifications are waiting to be shown.
+    pub fn waiting(&self) -> usize {
+        self.waiting.len()
+    }
+
+    fn sort(&mut self) {
+        // Stable, so notifications of the same priority keep their order.
+        self.waiting
+            .sort_by_key(|waiting| Reverse(waiting.priority));
+    }
+
+    /// `frame` with the current notification drawn over it, or `frame` itself
+    /// when there's none to show.
+    pub fn apply<'a>(&'a mut self, tick: &FrameTick, frame: &'a Canvas) -> &'a Canvas {
+        self.t = tick.t;
+        if self
+            .showing
+            .as_ref()
+            .is_some_and(|showing| showing.done(tick.t))
+        {
+            self.showing = None;
+        }
+        if self.showing.is_none() && !self.waiting.is_empty() {
+            self.showing = Some(Showing::new(self.waiting.remove(0), tick.t));
+        }
+        self.drawn = self.showing.is_some();
+        let Some(showing) = &self.showing else {
+            return frame;
+        };
+
+        self.frame.clone_from(frame);
+        showing.flash(&mut self.frame, tick.t);
+        if (self.layer.width, self.layer.height) != (frame.width, frame.height) {
+            self.layer = Canvas::new(frame.width, frame.height);
+        }
+        self.layer.clear();
+        showing.draw(&mut self.layer, tick.t);
+        if showing.notification.animation == Animation::Fade {
+            let visibility = showing.visibility(tick.t);
+            for pixel in &mut self.layer.pixels {
+                pixel[3] *= visibility;
+            }
+        }
+        self.frame.composite(&self.layer);
+        &self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(text: &str, priority: Priority) -> Notification {
+        Notification {
+            text: text.to_string(),
+            priority,
+            duration: default_duration(),
+            icon: None,
+            style: Style::default(),
+            animation: Animation::default(),
+            flash: false,
+            color: None,
+            font: None,
+            key: None,
+        }
+    }
+
+    #[test]
+    fn preemption_counts_against_the_limit() {
+        let mut overlay = Overlay::new(16, 8);
+        let frame = Canvas::new(16, 8);
+        overlay
+            .push(notification("showing", Priority::Low))
+            .unwrap();
+        overlay.apply(&FrameTick::at(0.0), &frame);
+        for i in 0..MAX_WAITING - 1 {
+            overlay
+                .push(notification(&format!("waiting {i}"), Priority::Low))
+                .unwrap();
+        }
+        assert_eq!(overlay.waiting(), MAX_WAITING - 1);
+
+        // Preempting would requeue the one showing as well.
+        assert!(overlay
+            .push(notification("urgent", Priority::Urgent))
+            .is_err());
+        assert_eq!(overlay.waiting(), MAX_WAITING - 1);
+        // Without preempting, the last place is free.
+        overlay.push(notification("low", Priority::Low)).unwrap();
+        assert_eq!(overlay.waiting(), MAX_WAITING);
+        assert!(overlay.push(notification("more", Priority::Low)).is_err());
+    }
+
+    #[test]
+    fn idle_until_the_frame_after_the_last_notification() {
+        let mut overlay = Overlay::new(16, 8);
+        let frame = Canvas::new(16, 8);
+        assert!(overlay.idle());
+        let mut notification = notification("brief", Priority::Low);
+        notification.duration = 1.0;
+        overlay.push(notification).unwrap();
+        assert!(!overlay.idle());
+        overlay.apply(&FrameTick::at(0.0), &frame);
+        assert!(!overlay.idle());
+        // The frame it's gone from still has to be drawn.
+        overlay.apply(&FrameTick::at(5.0), &frame);
+        assert!(overlay.idle());
+    }
+
+    #[test]
+    fn duplicates_merge() {
+        let mut overlay = Overlay::new(16, 8);
+        let first = notification("same", Priority::Low);
+        assert_eq!(overlay.push(first.clone()), Ok(Outcome::Queued));
+        assert_eq!(
+            overlay.push(notification("same", Priority::High)),
+            Ok(Outcome::Merged)
+        );
+        assert_eq!(overlay.waiting(), 1);
+        assert_eq!(overlay.waiting[0].priority, Priority::High);
+    }
+
+    #[test]
+    fn preempted_notifications_show_again() {
+        let mut overlay = Overlay::new(16, 8);
+        let frame = Canvas::new(16, 8);
+        overlay.push(notification("low", Priority::Low)).unwrap();
+        overlay.apply(&FrameTick::at(0.0), &frame);
+        overlay
+            .push(notification("urgent", Priority::Urgent))
+            .unwrap();
+        assert_eq!(overlay.waiting[0].text, "urgent");
+        assert_eq!(overlay.waiting[1].text, "low");
+    }
+}
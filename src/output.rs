@@ -0,0 +1,573 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time,
+};
+
+use led_matrix_zmq::client::{MatrixClient, MatrixClientSettings};
+use log2::*;
+
+use crate::{
+    calibration::Calibration,
+    color::{decode_srgb, encode_srgb},
+    config::OutputConfig,
+    dither::{DitherMode, Quantizer},
+    layout::{PixelMap, Tile},
+    metrics::Metrics,
+    transform::Transform,
+    Canvas,
+};
+
+// A send that takes longer than this counts as a failed connection.
+const SEND_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+// Unchanged frames are still re-sent this often, so a restarted server
+// doesn't stay blank.
+const KEEP_ALIVE_INTERVAL: time::Duration = time::Duration::from_secs(5);
+const MIN_BACKOFF: time::Duration = time::Duration::from_millis(500);
+const MAX_BACKOFF: time::Duration = time::Duration::from_secs(30);
+// Brightness sent to the server when brightness is scaled in software.
+const FULL_BRIGHTNESS: u8 = 100;
+// Workers stuck inside a send can't be cancelled. Cap how many we leave behind
+// before waiting for one to come unstuck instead of reconnecting again.
+const MAX_STALE_WORKERS: usize = 4;
+
+struct Frame {
+    /// Only set when it differs from the last frame, or on a keep-alive.
+    brightness: Option<u8>,
+    pixels: Vec<u8>,
+}
+
+/// What a link last handed to its worker, to skip frames that match it.
+struct LastSent {
+    brightness: u8,
+    pixels: Vec<u8>,
+    /// What went into the quantizer, if the link has one.
+    input: Vec<f32>,
+    at: time::Instant,
+}
+
+#[derive(Default)]
+struct WorkerState {
+    failed: AtomicBool,
+    sent: AtomicBool,
+    pending: AtomicUsize,
+    busy_since: Mutex<Option<time::Instant>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum LinkState {
+    Connecting,
+    Up,
+    Down { retry_at: time::Instant },
+}
+
+/// One `led_matrix_zmq` server, with its own client, worker thread and health.
+struct Link {
+    addr: String,
+    tile: Option<Tile>,
+    transform: Transform,
+    layout: Option<PixelMap>,
+    calibration: Option<Calibration>,
+    quantizer: Option<Quantizer>,
+    software_brightness: bool,
+    // Hold the cropped, transformed and remapped frames, so they aren't
+    // reallocated on every send.
+    tiled: Canvas,
+    transformed: Canvas,
+    wire: Canvas,
+    precise: Vec<f32>,
+    last_sent: Option<LastSent>,
+    // Whether the last frame it was given was dropped, so the server shows an
+    // older one.
+    behind: bool,
+    state: LinkState,
+    backoff: time::Duration,
+    // Whether the current outage has been logged, so retries don't spam.
+    reported_down: bool,
+    tx: Option<SyncSender<Frame>>,
+    worker: Option<JoinHandle<()>>,
+    worker_state: Arc<WorkerState>,
+    stale_workers: Vec<JoinHandle<()>>,
+}
+
+impl Link {
+    /// A link that isn't connected yet. `connect` starts its worker.
+    fn new(
+        config: &OutputConfig,
+        layout: Option<PixelMap>,
+        calibration: Option<Calibration>,
+    ) -> Self {
+        Link {
+            addr: config.addr.clone(),
+            tile: config.tile,
+            transform: config.transform,
+            layout,
+            quantizer: (calibration.is_some()
+                || config.dither.mode != DitherMode::None
+                || config.dither.software_brightness)
+                .then(|| Quantizer::new(config.dither.mode)),
+            calibration,
+            software_brightness: config.dither.software_brightness,
+            tiled: Canvas::new(0, 0),
+            transformed: Canvas::new(0, 0),
+            wire: Canvas::new(0, 0),
+            precise: vec![],
+            last_sent: None,
+            behind: false,
+            state: LinkState::Connecting,
+            backoff: MIN_BACKOFF,
+            reported_down: false,
+            tx: None,
+            worker: None,
+            worker_state: Arc::new(WorkerState::default()),
+            stale_workers: vec![],
+        }
+    }
+
+    fn connect(&mut self, metrics: &Arc<Metrics>) {
+        // Bounded to a single frame: if the worker is still busy, the next
+        // frame is dropped rather than queued behind it.
+        let (tx, rx) = mpsc::sync_channel(1);
+        let worker_state = Arc::new(WorkerState::default());
+
+        let addr = self.addr.clone();
+        let thread_state = worker_state.clone();
+        let thread_metrics = metrics.clone();
+        self.worker = Some(thread::spawn(move || {
+            run_worker(addr, rx, &thread_state, &thread_metrics)
+        }));
+        self.tx = Some(tx);
+        self.worker_state = worker_state;
+        // A new worker has a new client, so send it everything again.
+        self.last_sent = None;
+        self.state = LinkState::Connecting;
+    }
+
+    fn mark_down(&mut self, reason: &str, metrics: &Metrics) {
+        if let Some(worker) = self.worker.take() {
+            self.stale_workers.push(worker);
+        }
+        self.tx = None;
+
+        if self.reported_down {
+            debug!(
+                "Matrix {} still down ({}), retrying in {:?}",
+                self.addr, reason, self.backoff
+            );
+        } else {
+            warn!(
+                "Matrix {} down ({}), retrying in {:?}",
+                self.addr, reason, self.backoff
+            );
+            self.reported_down = true;
+        }
+        metrics.set_matrix_up(&self.addr, false);
+        self.state = LinkState::Down {
+            retry_at: time::Instant::now() + self.backoff,
+        };
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+
+    fn poll(&mut self, metrics: &Arc<Metrics>) {
+        self.stale_workers.retain(|worker| !worker.is_finished());
+
+        match self.state {
+            LinkState::Down { retry_at } => {
+                if time::Instant::now() >= retry_at && self.stale_workers.len() < MAX_STALE_WORKERS
+                {
+                    debug!("Matrix {} reconnecting", self.addr);
+                    metrics.inc_matrix_reconnects(&self.addr);
+                    self.connect(metrics);
+                }
+            }
+            LinkState::Connecting | LinkState::Up => {
+                let busy_since = *self.worker_state.busy_since.lock().unwrap();
+                if self.worker_state.failed.load(Ordering::Acquire) {
+                    self.mark_down("send failed", metrics);
+                } else if busy_since.is_some_and(|since| since.elapsed() > SEND_TIMEOUT) {
+                    self.mark_down("send timed out", metrics);
+                } else if self.worker_state.sent.swap(false, Ordering::AcqRel)
+                    && self.state == LinkState::Connecting
+                {
+                    warn!("Matrix {} up", self.addr);
+                    metrics.set_matrix_up(&self.addr, true);
+                    self.state = LinkState::Up;
+                    self.backoff = MIN_BACKOFF;
+                    self.reported_down = false;
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, brightness: u8, canvas: &Canvas, metrics: &Arc<Metrics>) {
+        let tx = match &self.tx {
+            Some(tx) => tx,
+            None => return,
+        };
+
+        let mut frame = canvas;
+        if let Some(tile) = &self.tile {
+            tile.apply(frame, &mut self.tiled);
+            frame = &self.tiled;
+        }
+        if !self.transform.is_identity() {
+            self.transform.apply(frame, &mut self.transformed);
+            frame = &self.transformed;
+        }
+        if let Some(layout) = &self.layout {
+            layout.apply(frame, &mut self.wire);
+            frame = &self.wire;
+        }
+        let now = time::Instant::now();
+        // Brightness is only re-sent when it changes, or with a keep-alive.
+        let recent = self
+            .last_sent
+            .as_ref()
+            .filter(|last| now.duration_since(last.at) < KEEP_ALIVE_INTERVAL);
+        let mut brightness = brightness;
+        let pixels = match &mut self.quantizer {
+            Some(quantizer) => {
+                // Scale and calibrate at full precision, then quantize once.
+                frame.encode_srgb(&mut self.precise);
+                if self.software_brightness {
+                    // In linear light, like the panel's own brightness.
+                    let scale = brightness as f32 / FULL_BRIGHTNESS as f32;
+                    for value in &mut self.precise {
+                        *value = encode_srgb(decode_srgb(*value) * scale);
+                    }
+                    brightness = FULL_BRIGHTNESS;
+                }
+                if let Some(calibration) = &self.calibration {
+                    calibration.apply(&mut self.precise);
+                }
+                // Dithering turns the same frame into different bytes every
+                // time, so a repeat is told apart by what goes into it.
+                if recent
+                    .is_some_and(|last| last.brightness == brightness && last.input == self.precise)
+                {
+                    metrics.inc_matrix_unchanged_frames(&self.addr);
+                    self.behind = false;
+                    return;
+                }
+
+                let mut pixels = vec![0; self.precise.len()];
+                quantizer.quantize(&self.precise, frame.width, &mut pixels);
+                pixels
+            }
+            None => {
+                let pixels = frame.to_srgb8();
+                if recent.is_some_and(|last| last.brightness == brightness && last.pixels == pixels)
+                {
+                    metrics.inc_matrix_unchanged_frames(&self.addr);
+                    self.behind = false;
+                    return;
+                }
+                pixels
+            }
+        };
+        let send_brightness = recent.is_none_or(|last| last.brightness != brightness);
+
+        // Counted before sending so the worker can never finish it first.
+        self.worker_state.pending.fetch_add(1, Ordering::AcqRel);
+        let result = tx.try_send(Frame {
+            brightness: send_brightness.then_some(brightness),
+            pixels: pixels.clone(),
+        });
+        if result.is_err() {
+            self.worker_state.pending.fetch_sub(1, Ordering::AcqRel);
+        }
+
+        match result {
+            Ok(()) => {
+                self.last_sent = Some(LastSent {
+                    brightness,
+                    pixels,
+                    input: self.precise.clone(),
+                    at: now,
+                });
+                self.behind = false;
+            }
+            Err(TrySendError::Full(_)) => self.drop_frame(metrics),
+            Err(TrySendError::Disconnected(_)) => self.mark_down("worker exited", metrics),
+        }
+    }
+
+    fn drop_frame(&mut self, metrics: &Metrics) {
+        metrics.inc_matrix_dropped_frames(&self.addr);
+        self.behind = true;
+    }
+
+    /// Whether the link has nothing to send for a frame the same as the last
+    /// one it was given: it's down, or its server shows that frame and isn't
+    /// due a keep-alive.
+    fn current(&self) -> bool {
+        self.tx.is_none()
+            || !self.behind
+                && self
+                    .last_sent
+                    .as_ref()
+                    .is_some_and(|last| last.at.elapsed() < KEEP_ALIVE_INTERVAL)
+    }
+
+    /// Whether the worker has finished every frame sent to it. Links that are
+    /// down count as idle, so they don't hold up the others.
+    fn is_idle(&self) -> bool {
+        self.tx.is_none() || self.worker_state.pending.load(Ordering::Acquire) == 0
+    }
+}
+
+fn run_worker(addr: String, rx: Receiver<Frame>, state: &WorkerState, metrics: &Metrics) {
+    let client = match panic::catch_unwind(|| {
+        MatrixClient::new(MatrixClientSettings {
+            addrs: vec![addr.clone()],
+        })
+    }) {
+        Ok(client) => client,
+        Err(_) => {
+            state.failed.store(true, Ordering::Release);
+            return;
+        }
+    };
+
+    for frame in rx {
+        *state.busy_since.lock().unwrap() = Some(time::Instant::now());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            metrics.time_send(|| {
+                if let Some(brightness) = frame.brightness {
+                    client.send_brightness(brightness);
+                }
+                client.send_frame(&frame.pixels);
+            })
+        }));
+        *state.busy_since.lock().unwrap() = None;
+        state.pending.fetch_sub(1, Ordering::AcqRel);
+
+        if result.is_err() {
+            state.failed.store(true, Ordering::Release);
+            return;
+        }
+        state.sent.store(true, Ordering::Release);
+    }
+}
+
+/// Sends frames to every configured matrix server, tracking the health of
+/// each one and reconnecting with backoff when a server stops responding.
+///
+/// A frame goes to all tiled servers or none: if one is still busy with the
+/// last frame, the new one is dropped for every tile, so tiles of a shared
+/// canvas never show different frames across their seams. Servers showing the
+/// whole canvas each keep their own pace.
+///
+/// A frame that matches the last one sent to a server, brightness included,
+/// isn't sent again, apart from a keep-alive every few seconds. Dithered
+/// frames are compared before dithering, which would make every frame
+/// different, so a still frame keeps the dither it was first sent with.
+pub struct MatrixOutput {
+    links: Vec<Link>,
+    metrics: Arc<Metrics>,
+    // The brightness `send` was last called with.
+    brightness: Option<u8>,
+}
+
+impl MatrixOutput {
+    /// Builds the outputs for a `width` x `height` canvas. Fails if a layout or
+    /// calibration LUT can't be loaded or a tile falls outside of the canvas.
+    pub fn new(
+        outputs: &[OutputConfig],
+        width: u32,
+        height: u32,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self, String> {
+        let mut links = vec![];
+        for output in outputs {
+            let (tile_width, tile_height) = match output.tile {
+                Some(tile) if !tile.fits(width, height) => {
+                    return Err(format!(
+                        "tile for {} doesn't fit the {width}x{height} canvas",
+                        output.addr
+                    ));
+                }
+                Some(tile) => (tile.width, tile.height),
+                None => (width, height),
+            };
+
+            let layout = match &output.layout {
+                Some(layout) => {
+                    let layout = PixelMap::from_config(layout)
+                        .map_err(|e| format!("layout for {}: {e}", output.addr))?;
+                    let expected = output.transform.output_size(tile_width, tile_height);
+                    if layout.logical_size() != expected {
+                        warn!(
+                            "Layout for {} covers {:?} but the transformed canvas is {:?}",
+                            output.addr,
+                            layout.logical_size(),
+                            expected
+                        );
+                    }
+                    Some(layout)
+                }
+                None => None,
+            };
+
+            let calibration = Calibration::from_config(&output.calibration)
+                .map_err(|e| format!("calibration for {}: {e}", output.addr))?;
+            let calibration = if calibration.is_identity() {
+                None
+            } else {
+                Some(calibration)
+            };
+
+            let mut link = Link::new(output, layout, calibration);
+            link.connect(&metrics);
+            links.push(link);
+        }
+
+        Ok(MatrixOutput {
+            links,
+            metrics,
+            brightness: None,
+        })
+    }
+
+    pub fn send(&mut self, brightness: u8, canvas: &Canvas) {
+        self.metrics.set_brightness(brightness);
+        self.brightness = Some(brightness);
+        for link in self.links.iter_mut() {
+            link.poll(&self.metrics);
+        }
+
+        let tiles_idle = self
+            .links
+            .iter()
+            .filter(|link| link.tile.is_some())
+            .all(Link::is_idle);
+        for link in self.links.iter_mut() {
+            if link.tile.is_some() && !tiles_idle {
+                if link.tx.is_some() {
+                    link.drop_frame(&self.metrics);
+                }
+                continue;
+            }
+            link.send(brightness, canvas, &self.metrics);
+        }
+    }
+
+    /// Stands in for `send` when the frame is the same as the last one, so it
+    /// isn't built again. Returns false, having sent nothing, if a server
+    /// needs the frame anyway, to catch up, after reconnecting or for a
+    /// keep-alive, or if `brightness` changed.
+    pub fn repeat(&mut self, brightness: u8) -> bool {
+        for link in self.links.iter_mut() {
+            link.poll(&self.metrics);
+        }
+        if self.brightness != Some(brightness) || !self.links.iter().all(Link::current) {
+            return false;
+        }
+        for link in self.links.iter().filter(|link| link.tx.is_some()) {
+            self.metrics.inc_matrix_unchanged_frames(&link.addr);
+        }
+        true
+    }
+
+    /// Waits up to `timeout` for every link to finish its in-flight frame.
+    pub fn flush(&self, timeout: time::Duration) {
+        let deadline = time::Instant::now() + timeout;
+        while !self.links.iter().all(Link::is_idle) && time::Instant::now() < deadline {
+            thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dither::DitherConfig;
+
+    /// A link to nowhere, and the receiving end of what it sends.
+    fn link(mode: DitherMode) -> (Link, Receiver<Frame>) {
+        let config = OutputConfig {
+            addr: "test".to_string(),
+            tile: None,
+            transform: Transform::default(),
+            layout: None,
+            calibration: Default::default(),
+            dither: DitherConfig {
+                mode,
+                software_brightness: false,
+            },
+        };
+        let mut link = Link::new(&config, None, None);
+        let (tx, rx) = mpsc::sync_channel(1);
+        link.tx = Some(tx);
+        (link, rx)
+    }
+
+    /// Sends `canvas` and returns whether a frame went out.
+    fn sent(link: &mut Link, rx: &Receiver<Frame>, canvas: &Canvas) -> bool {
+        link.send(50, canvas, &Arc::new(Metrics::new()));
+        let frame = rx.try_recv().ok();
+        if frame.is_some() {
+            link.worker_state.pending.fetch_sub(1, Ordering::AcqRel);
+        }
+        frame.is_some()
+    }
+
+    /// A gray between two 8-bit levels, so dithering alternates them.
+    fn gray(srgb: f32) -> Canvas {
+        let mut canvas = Canvas::new(4, 4);
+        canvas.clear_with_color(srgb, srgb, srgb);
+        canvas
+    }
+
+    #[test]
+    fn repeats_need_the_server_to_be_current() {
+        let (link, rx) = link(DitherMode::None);
+        let mut output = MatrixOutput {
+            links: vec![link],
+            metrics: Arc::new(Metrics::new()),
+            brightness: None,
+        };
+        let done = |output: &MatrixOutput| {
+            output.links[0]
+                .worker_state
+                .pending
+                .fetch_sub(1, Ordering::AcqRel)
+        };
+        assert!(!output.repeat(50));
+
+        output.send(50, &gray(0.5));
+        assert!(rx.try_recv().is_ok());
+        done(&output);
+        assert!(output.repeat(50));
+        assert!(!output.repeat(60));
+
+        // A frame dropped while the last is still being sent leaves the
+        // server behind.
+        output.send(50, &gray(0.25));
+        output.send(50, &gray(0.75));
+        assert!(!output.repeat(50));
+        assert!(rx.try_recv().is_ok());
+        done(&output);
+        output.send(50, &gray(0.75));
+        assert!(rx.try_recv().is_ok());
+        assert!(output.repeat(50));
+    }
+
+    #[test]
+    fn still_frames_are_sent_once() {
+        for mode in [DitherMode::None, DitherMode::Temporal, DitherMode::Ordered] {
+            let (mut link, rx) = link(mode);
+            let frame = gray(100.5 / 255.0);
+            assert!(sent(&mut link, &rx, &frame), "{mode:?}");
+            for _ in 0..8 {
+                assert!(!sent(&mut link, &rx, &frame), "{mode:?}");
+            }
+            assert!(sent(&mut link, &rx, &gray(0.5)), "{mode:?}");
+        }
+    }
+}
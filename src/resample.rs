@@ -0,0 +1,115 @@
+use serde::Deserialize;
+
+use crate::Canvas;
+
+/// How a canvas rendered below the output size is scaled up. Scaling down,
+/// as when supersampling, always averages the covered pixels.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Upscale {
+    #[default]
+    Nearest,
+    Bilinear,
+}
+
+/// Size a scene renders at for a `width` x `height` output and `scale`, where
+/// 2.0 supersamples and 0.5 renders at half resolution.
+pub fn scaled_size(width: u32, height: u32, scale: f32) -> (u32, u32) {
+    let scaled = |v: u32| ((v as f32 * scale).round() as u32).max(1);
+    (scaled(width), scaled(height))
+}
+
+/// Resamples `src` to the size of `dst`, averaging and interpolating in
+/// linear light.
+pub fn resample(src: &Canvas, dst: &mut Canvas, upscale: Upscale) {
+    if src.width == dst.width && src.height == dst.height {
+        dst.pixels.copy_from_slice(&src.pixels);
+    } else if src.width >= dst.width && src.height >= dst.height {
+        downsample_box(src, dst);
+    } else {
+        match upscale {
+            Upscale::Nearest => upsample_nearest(src, dst),
+            Upscale::Bilinear => upsample_bilinear(src, dst),
+        }
+    }
+}
+
+fn downsample_box(src: &Canvas, dst: &mut Canvas) {
+    for y in 0..dst.height {
+        let y0 = y * src.height / dst.height;
+        let y1 = ((y + 1) * src.height / dst.height).max(y0 + 1);
+
+        for x in 0..dst.width {
+            let x0 = x * src.width / dst.width;
+            let x1 = ((x + 1) * src.width / dst.width).max(x0 + 1);
+
+            // Colors are weighted by alpha, so transparent pixels don't darken
+            // the edges of what they surround.
+            let mut sum = [0.0f32; 4];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let [r, g, b, a] = src.pixels[(sy * src.width + sx) as usize];
+                    sum[0] += r * a;
+                    sum[1] += g * a;
+                    sum[2] += b * a;
+                    sum[3] += a;
+                }
+            }
+
+            let count = ((y1 - y0) * (x1 - x0)) as f32;
+            dst.pixels[(y * dst.width + x) as usize] = unpremultiply(sum, count);
+        }
+    }
+}
+
+fn upsample_nearest(src: &Canvas, dst: &mut Canvas) {
+    let (dst_width, dst_height) = (dst.width, dst.height);
+    for (x, y, px) in dst.enumerate_pixels_mut() {
+        let sy = y * src.height / dst_height;
+        let sx = x * src.width / dst_width;
+        *px = src.pixels[(sy * src.width + sx) as usize];
+    }
+}
+
+fn upsample_bilinear(src: &Canvas, dst: &mut Canvas) {
+    // Sample at pixel centers, so edges don't shift by half a source pixel.
+    let to_src = |v: u32, dst_len: u32, src_len: u32| -> (u32, u32, f32) {
+        let pos = ((v as f32 + 0.5) * src_len as f32 / dst_len as f32 - 0.5).max(0.0);
+        let i0 = (pos as u32).min(src_len - 1);
+        let i1 = (i0 + 1).min(src_len - 1);
+        (i0, i1, pos - i0 as f32)
+    };
+    let (dst_width, dst_height) = (dst.width, dst.height);
+
+    for (x, y, px) in dst.enumerate_pixels_mut() {
+        let (y0, y1, fy) = to_src(y, dst_height, src.height);
+        let (x0, x1, fx) = to_src(x, dst_width, src.width);
+
+        let weights = [
+            (x0, y0, (1.0 - fx) * (1.0 - fy)),
+            (x1, y0, fx * (1.0 - fy)),
+            (x0, y1, (1.0 - fx) * fy),
+            (x1, y1, fx * fy),
+        ];
+        let mut sum = [0.0f32; 4];
+        for (sx, sy, weight) in weights {
+            let [r, g, b, a] = src.pixels[(sy * src.width + sx) as usize];
+            let weight = weight * a;
+            sum[0] += r * weight;
+            sum[1] += g * weight;
+            sum[2] += b * weight;
+            sum[3] += weight;
+        }
+        *px = unpremultiply(sum, 1.0);
+    }
+}
+
+/// Turns a sum of alpha weighted colors over `count` samples back into a
+/// straight alpha pixel.
+fn unpremultiply([r, g, b, a]: [f32; 4], count: f32) -> [f32; 4] {
+    if a <= 0.0 {
+        [0.0; 4]
+    } else {
+        [r / a, g / a, b / a, a / count]
+    }
+}
@@ -0,0 +1,408 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    time,
+};
+
+use log2::*;
+
+use crate::{
+    config::SceneConfig,
+    frame_tick::FrameTick,
+    resample::{self, Upscale},
+    Canvas, Scene, UpdateRate,
+};
+
+// Upper bound on fixed steps per output frame, so a long stall doesn't turn
+// into a burst of simulation that makes the next frame even later.
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+// Updates in a row over the scene's budget before it's treated as failed.
+const SLOW_UPDATES_TO_FAIL: u32 = 3;
+// How long a failed scene stays off before it's rebuilt, doubling with each
+// failure in a row.
+const RESTART_DELAY: time::Duration = time::Duration::from_secs(5);
+const MAX_RESTART_DELAY: time::Duration = time::Duration::from_secs(300);
+// A scene that has run this long since its restart starts over at
+// `RESTART_DELAY` when it fails again.
+const STABLE_AFTER: time::Duration = time::Duration::from_secs(60);
+
+type Build<'a> = Box<dyn Fn(u32, u32) -> Result<Box<dyn Scene>, String> + 'a>;
+
+/// Drives a scene at its own `UpdateRate`, independently of the output fps.
+///
+/// The scene renders into a canvas owned by the runner, so frames where the
+/// scene isn't due keep the previous image instead of re-rendering it. That
+/// canvas can be larger or smaller than the output, per `render_scale`, and is
+/// resampled to the output size after each update.
+///
+/// A scene that panics, or is over its time budget several updates in a row,
+/// is turned off and `render` returns nothing until it's rebuilt, after a
+/// delay that grows with each failure, so the caller can show something else
+/// in the meantime.
+pub struct SceneRunner<'a> {
+    build: Build<'a>,
+    scene: Box<dyn Scene>,
+    scene_canvas: Canvas,
+    canvas: Canvas,
+    upscale: Upscale,
+    render_scale: f32,
+    active: bool,
+    last_update: Option<time::Instant>,
+    accumulator: f32,
+    changed: bool,
+    // Whether the last update ran the scene's `tick`, `step` or `draw`, so
+    // only those updates are held to the budget.
+    worked: bool,
+    budget: time::Duration,
+    slow_updates: u32,
+    // When the scene failed, if it's off.
+    failed_at: Option<time::Instant>,
+    // Failures in a row, each restart followed by another one before
+    // `STABLE_AFTER`.
+    failures: u32,
+    restarted_at: Option<time::Instant>,
+}
+
+impl<'a> SceneRunner<'a> {
+    /// Creates the scene with `build`, passing it the size of the canvas it
+    /// will render to, for a `width` x `height` output, and sets the
+    /// parameters from `config`. Fails if `build` does or a parameter isn't
+    /// valid. `build` is kept to restart the scene if it fails.
+    pub fn new(
+        config: &SceneConfig,
+        width: u32,
+        height: u32,
+        build: impl Fn(u32, u32) -> Result<Box<dyn Scene>, String> + 'a,
+    ) -> Result<Self, String> {
+        let (scene_width, scene_height) = resample::scaled_size(width, height, config.render_scale);
+
+        let mut scene = build(scene_width, scene_height)?;
+        for (name, value) in &config.params {
+            scene
+                .set_param(name, value.clone())
+                .map_err(|e| format!("{}: {e}", scene.name()))?;
+        }
+
+        Ok(SceneRunner {
+            build: Box::new(build),
+            scene,
+            scene_canvas: Canvas::new(scene_width, scene_height),
+            canvas: Canvas::new(width, height),
+            upscale: config.upscale,
+            render_scale: config.render_scale,
+            active: false,
+            last_update: None,
+            accumulator: 0.0,
+            changed: false,
+            worked: false,
+            budget: time::Duration::from_millis(config.budget_ms),
+            slow_updates: 0,
+            failed_at: None,
+            failures: 0,
+            restarted_at: None,
+        })
+    }
+
+    pub fn scene(&self) -> &dyn Scene {
+        self.scene.as_ref()
+    }
+
+    pub fn scene_mut(&mut self) -> &mut dyn Scene {
+        self.scene.as_mut()
+    }
+
+    /// Tells the scene it is no longer shown. The next `render` enters it
+    /// again.
+    pub fn exit(&mut self) {
+        if self.active && self.failed_at.is_none() {
+            self.guard(|runner| runner.scene.on_exit());
+        }
+        self.active = false;
+    }
+
+    /// Resizes the output, and the scene with it.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let (scene_width, scene_height) = resample::scaled_size(width, height, self.render_scale);
+        self.scene_canvas = Canvas::new(scene_width, scene_height);
+        self.canvas = Canvas::new(width, height);
+        if self.failed_at.is_none() {
+            self.guard(|runner| runner.scene.resize(scene_width, scene_height));
+        }
+        self.last_update = None;
+    }
+
+    /// Whether the scene is off after failing, waiting to be restarted.
+    pub fn failed(&self) -> bool {
+        self.failed_at.is_some()
+    }
+
+    /// Brings the scene up to date with `tick` and returns its canvas, or
+    /// `None` while the scene is off after failing.
+    pub fn render(&mut self, tick: &FrameTick) -> Option<&Canvas> {
+        if let Some(failed_at) = self.failed_at {
+            if failed_at.elapsed() < self.restart_delay() {
+                return None;
+            }
+            self.restart();
+            if self.failed_at.is_some() {
+                return None;
+            }
+        }
+
+        let start = time::Instant::now();
+        self.guard(|runner| runner.update(tick));
+        if self.failed_at.is_some() {
+            return None;
+        }
+
+        // Frames where a limited or fixed step scene wasn't due say nothing
+        // about how long its updates take.
+        if !self.worked {
+            return Some(&self.canvas);
+        }
+        let elapsed = start.elapsed();
+        if elapsed > self.budget {
+            self.slow_updates += 1;
+            warn!(
+                "Scene {} took {:?}, over its {:?} budget",
+                self.scene.name(),
+                elapsed,
+                self.budget
+            );
+            if self.slow_updates >= SLOW_UPDATES_TO_FAIL {
+                self.fail(format!(
+                    "over its budget {} updates in a row",
+                    self.slow_updates
+                ));
+                return None;
+            }
+        } else {
+            self.slow_updates = 0;
+        }
+
+        Some(&self.canvas)
+    }
+
+    /// Whether the last `render` updated the canvas.
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    /// The canvas as of the last `render`.
+    pub fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+
+    /// Runs `f`, failing the scene if it panics.
+    fn guard(&mut self, f: impl FnOnce(&mut Self)) {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(self))) {
+            self.fail(format!("panicked: {}", panic_message(payload.as_ref())));
+        }
+    }
+
+    fn fail(&mut self, reason: String) {
+        let now = time::Instant::now();
+        if self
+            .restarted_at
+            .is_some_and(|restarted_at| now.duration_since(restarted_at) >= STABLE_AFTER)
+        {
+            self.failures = 0;
+        }
+        self.failures += 1;
+        self.failed_at = Some(now);
+        self.active = false;
+        self.changed = false;
+        error!(
+            "Scene {} {}, restarting it in {:?}",
+            self.scene.name(),
+            reason,
+            self.restart_delay()
+        );
+    }
+
+    fn restart_delay(&self) -> time::Duration {
+        let doublings = self.failures.saturating_sub(1).min(16);
+        (RESTART_DELAY * 2u32.pow(doublings)).min(MAX_RESTART_DELAY)
+    }
+
+    /// Builds the scene again, with the parameters it had.
+    fn restart(&mut self) {
+        let (width, height) = (self.scene_canvas.width, self.scene_canvas.height);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<_, String> {
+            let params: Vec<_> = self
+                .scene
+                .params()
+                .iter()
+                .filter_map(|spec| Some((spec.name, self.scene.get_param(spec.name)?)))
+                .collect();
+            let mut scene = (self.build)(width, height)?;
+            for (name, value) in params {
+                if let Err(e) = scene.set_param(name, value) {
+                    warn!("Scene {}: {}", scene.name(), e);
+                }
+            }
+            Ok(scene)
+        }));
+
+        match result {
+            Ok(Ok(scene)) => {
+                info!("Restarted scene {}", scene.name());
+                self.scene = scene;
+                self.scene_canvas.clear();
+                self.failed_at = None;
+                self.restarted_at = Some(time::Instant::now());
+                self.slow_updates = 0;
+            }
+            Ok(Err(e)) => self.fail(format!("failed to restart: {e}")),
+            Err(payload) => self.fail(format!(
+                "panicked while restarting: {}",
+                panic_message(payload.as_ref())
+            )),
+        }
+    }
+
+    fn update(&mut self, tick: &FrameTick) {
+        self.worked = false;
+        if !self.active {
+            self.scene.on_enter();
+            self.active = true;
+            // Don't make up for the time spent away.
+            self.last_update = None;
+            self.accumulator = 0.0;
+        }
+
+        let updated = match self.scene.update_rate() {
+            UpdateRate::EveryFrame => {
+                let changed = self.scene.wants_redraw(tick);
+                if changed {
+                    self.scene.tick(&mut self.scene_canvas, tick);
+                }
+                self.worked = changed;
+                changed
+            }
+            UpdateRate::Limited(hz) => {
+                let period = time::Duration::from_secs_f32(1.0 / hz);
+                let due = match self.last_update {
+                    None => true,
+                    Some(last_update) => tick.instant.duration_since(last_update) >= period,
+                };
+
+                if due {
+                    self.last_update = Some(tick.instant);
+                }
+                let changed = due && self.scene.wants_redraw(tick);
+                if changed {
+                    self.scene.tick(&mut self.scene_canvas, tick);
+                }
+                self.worked = changed;
+                changed
+            }
+            UpdateRate::FixedStep(hz) => {
+                // The simulation keeps running even when there is nothing
+                // new to draw.
+                let alpha = self.advance(hz, tick);
+                let changed = self.scene.wants_redraw(tick);
+                if changed {
+                    self.scene.draw(&mut self.scene_canvas, tick, alpha);
+                }
+                self.worked |= changed;
+                changed
+            }
+        };
+
+        if updated {
+            resample::resample(&self.scene_canvas, &mut self.canvas, self.upscale);
+        }
+        self.changed = updated;
+    }
+
+    /// Runs the fixed steps due by `tick` and returns the interpolation
+    /// factor for drawing.
+    fn advance(&mut self, hz: f32, tick: &FrameTick) -> f32 {
+        let step = 1.0 / hz;
+        self.accumulator = (self.accumulator + tick.dt).min(step * MAX_STEPS_PER_FRAME as f32);
+
+        while self.accumulator >= step {
+            self.scene.step(step);
+            self.accumulator -= step;
+            self.worked = true;
+        }
+
+        self.accumulator / step
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    /// Takes `delay` to tick, at 4 updates a second like the clock.
+    struct Slow {
+        delay: Duration,
+    }
+
+    impl Scene for Slow {
+        fn name(&self) -> &'static str {
+            "slow"
+        }
+
+        fn description(&self) -> &'static str {
+            "A scene over its budget."
+        }
+
+        fn update_rate(&self) -> UpdateRate {
+            UpdateRate::Limited(4.0)
+        }
+
+        fn tick(&mut self, _canvas: &mut Canvas, _tick: &FrameTick) {
+            thread::sleep(self.delay);
+        }
+    }
+
+    fn runner(delay: Duration) -> SceneRunner<'static> {
+        let config = SceneConfig {
+            budget_ms: 20,
+            ..Default::default()
+        };
+        SceneRunner::new(&config, 4, 4, move |_, _| Ok(Box::new(Slow { delay }))).unwrap()
+    }
+
+    /// Renders 30 fps frames for `seconds`, and returns how many of them
+    /// came back with a canvas.
+    fn render_for(runner: &mut SceneRunner, seconds: f32) -> usize {
+        (0..(seconds * 30.0) as u32)
+            .filter(|frame| {
+                runner
+                    .render(&FrameTick::at(*frame as f32 / 30.0))
+                    .is_some()
+            })
+            .count()
+    }
+
+    #[test]
+    fn slow_limited_scenes_fail() {
+        let mut runner = runner(Duration::from_millis(40));
+        // Only one frame in about eight updates the scene, and the frames
+        // in between don't reset the count of slow ones.
+        render_for(&mut runner, 1.0);
+        assert!(runner.failed());
+    }
+
+    #[test]
+    fn limited_scenes_within_budget_keep_running() {
+        let mut runner = runner(Duration::ZERO);
+        assert_eq!(render_for(&mut runner, 1.0), 30);
+        assert!(!runner.failed());
+    }
+}
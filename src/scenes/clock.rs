@@ -1,29 +1,306 @@
-use crate::{frame_tick::FrameTick, Canvas, Scene};
-use chrono::Local;
-use embedded_graphics::{geometry::Point, pixelcolor::Rgb888, prelude::*, text::Text};
-use u8g2_fonts::{fonts, U8g2TextStyle};
+use std::{borrow::Cow, fmt};
 
-extern crate chrono;
+use crate::{
+    font,
+    frame_tick::FrameTick,
+    scenes::param::{self, ParamKind, ParamSpec, ParamValue},
+    Canvas, Scene, UpdateRate,
+};
+use chrono::{DateTime, Local, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use embedded_graphics::{
+    geometry::Point,
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+};
+use u8g2_fonts::{
+    types::{FontColor, VerticalPosition},
+    FontRenderer,
+};
 
-pub struct ClockScene();
+// Rows between the time and the date.
+const LINE_GAP: i32 = 2;
+// The seconds bar along the bottom edge, and the rows kept clear above it.
+const BAR_HEIGHT: u32 = 1;
+const BAR_GAP: u32 = 1;
+
+const PARAMS: [ParamSpec; 8] = [
+    ParamSpec {
+        name: "format",
+        description: "strftime format of the time, like %H:%M or %-I:%M %p.",
+        kind: ParamKind::TimeFormat,
+        default: ParamValue::Text(Cow::Borrowed("%I:%M")),
+    },
+    ParamSpec {
+        name: "date_format",
+        description: "strftime format of a second line, like %a %d %b, or empty for none.",
+        kind: ParamKind::TimeFormat,
+        default: ParamValue::Text(Cow::Borrowed("")),
+    },
+    ParamSpec {
+        name: "timezone",
+        description: "IANA timezone, like Europe/London, or empty for the system's.",
+        kind: ParamKind::Timezone,
+        default: ParamValue::Text(Cow::Borrowed("")),
+    },
+    ParamSpec {
+        name: "font",
+        description: "Font of the time.",
+        kind: ParamKind::Font,
+        default: ParamValue::Text(Cow::Borrowed("helvB14_tn")),
+    },
+    ParamSpec {
+        name: "date_font",
+        description: "Font of the date.",
+        kind: ParamKind::Font,
+        default: ParamValue::Text(Cow::Borrowed("5x7_tf")),
+    },
+    ParamSpec {
+        name: "color",
+        description: "Color of the time, date and seconds bar.",
+        kind: ParamKind::Color,
+        default: ParamValue::Color([1.0, 1.0, 1.0]),
+    },
+    ParamSpec {
+        name: "blink",
+        description: "Hide colons every other second.",
+        kind: ParamKind::Bool,
+        default: ParamValue::Bool(false),
+    },
+    ParamSpec {
+        name: "seconds_bar",
+        description: "A bar along the bottom that fills over each minute.",
+        kind: ParamKind::Bool,
+        default: ParamValue::Bool(false),
+    },
+];
+
+/// Everything drawn at one moment, so unchanged faces aren't redrawn.
+#[derive(Clone, PartialEq)]
+struct Face {
+    time: String,
+    date: String,
+    colons: bool,
+    // Width of the seconds bar.
+    bar: u32,
+}
+
+pub struct ClockScene {
+    width: u32,
+    format: String,
+    date_format: String,
+    timezone: Option<Tz>,
+    font: String,
+    date_font: String,
+    color: [f32; 3],
+    blink: bool,
+    seconds_bar: bool,
+    drawn: Option<Face>,
+}
 
 impl ClockScene {
-    pub fn new(_canvas: &Canvas) -> Self {
-        ClockScene()
+    pub fn new(width: u32, _height: u32) -> Self {
+        ClockScene {
+            width,
+            format: PARAMS[0].default.as_text().to_string(),
+            date_format: PARAMS[1].default.as_text().to_string(),
+            timezone: None,
+            font: PARAMS[3].default.as_text().to_string(),
+            date_font: PARAMS[4].default.as_text().to_string(),
+            color: PARAMS[5].default.as_color(),
+            blink: PARAMS[6].default.as_bool(),
+            seconds_bar: PARAMS[7].default.as_bool(),
+            drawn: None,
+        }
+    }
+
+    fn face(&self) -> Face {
+        match self.timezone {
+            Some(timezone) => self.face_at(Utc::now().with_timezone(&timezone)),
+            None => self.face_at(Local::now()),
+        }
+    }
+
+    fn face_at<Z: TimeZone>(&self, now: DateTime<Z>) -> Face
+    where
+        Z::Offset: fmt::Display,
+    {
+        Face {
+            time: now.format(&self.format).to_string(),
+            date: now.format(&self.date_format).to_string(),
+            colons: !self.blink || now.second().is_multiple_of(2),
+            bar: if self.seconds_bar {
+                self.width * now.second() / 60
+            } else {
+                0
+            },
+        }
+    }
+}
+
+/// The box `text` covers when drawn with its top left at the origin. Fonts
+/// differ in how far glyphs sit from where they're drawn, so lines are placed
+/// by this rather than by the point they're drawn at.
+fn bounds(font: &FontRenderer, text: &str) -> Option<Rectangle> {
+    font.get_rendered_dimensions(text, Point::zero(), VerticalPosition::Top)
+        .ok()?
+        .bounding_box
+}
+
+fn advance(font: &FontRenderer, text: &str) -> i32 {
+    font.get_rendered_dimensions(text, Point::zero(), VerticalPosition::Top)
+        .map_or(0, |dimensions| dimensions.advance.x)
+}
+
+/// Draws `text` with its top left at `at`, leaving out colons unless `colons`
+/// while keeping everything else where it would be.
+fn draw_line(
+    canvas: &mut Canvas,
+    font: &FontRenderer,
+    text: &str,
+    at: Point,
+    colons: bool,
+    color: Rgb888,
+) {
+    let mut x = at.x;
+    for (i, piece) in text.split(':').enumerate() {
+        if i > 0 {
+            if colons {
+                font.render(
+                    ":",
+                    Point::new(x, at.y),
+                    VerticalPosition::Top,
+                    FontColor::Transparent(color),
+                    canvas,
+                )
+                .ok();
+            }
+            x += advance(font, ":");
+        }
+        if !piece.is_empty() {
+            font.render(
+                piece,
+                Point::new(x, at.y),
+                VerticalPosition::Top,
+                FontColor::Transparent(color),
+                canvas,
+            )
+            .ok();
+        }
+        x += advance(font, piece);
     }
 }
 
 impl Scene for ClockScene {
+    fn name(&self) -> &'static str {
+        "clock"
+    }
+
+    fn description(&self) -> &'static str {
+        "The time, and optionally the date, centered on the canvas."
+    }
+
+    fn params(&self) -> &'static [ParamSpec] {
+        &PARAMS
+    }
+
+    fn get_param(&self, name: &str) -> Option<ParamValue> {
+        let text = |text: &str| Some(ParamValue::Text(text.to_string().into()));
+        match name {
+            "format" => text(&self.format),
+            "date_format" => text(&self.date_format),
+            "timezone" => text(self.timezone.map_or("", |timezone| timezone.name())),
+            "font" => text(&self.font),
+            "date_font" => text(&self.date_font),
+            "color" => Some(ParamValue::Color(self.color)),
+            "blink" => Some(ParamValue::Bool(self.blink)),
+            "seconds_bar" => Some(ParamValue::Bool(self.seconds_bar)),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = param::validate(&PARAMS, name, value)?;
+        match name {
+            "format" => self.format = value.as_text().to_string(),
+            "date_format" => self.date_format = value.as_text().to_string(),
+            "timezone" => self.timezone = value.as_text().parse().ok(),
+            "font" => self.font = value.as_text().to_string(),
+            "date_font" => self.date_font = value.as_text().to_string(),
+            "color" => self.color = value.as_color(),
+            "blink" => self.blink = value.as_bool(),
+            "seconds_bar" => self.seconds_bar = value.as_bool(),
+            _ => {}
+        }
+        self.drawn = None;
+        Ok(())
+    }
+
+    fn on_enter(&mut self) {
+        // Whatever was drawn before may be gone.
+        self.drawn = None;
+    }
+
+    fn resize(&mut self, width: u32, _height: u32) {
+        self.width = width;
+        self.drawn = None;
+    }
+
+    fn update_rate(&self) -> UpdateRate {
+        // Often enough that a blinking colon doesn't miss a second.
+        UpdateRate::Limited(4.0)
+    }
+
+    fn wants_redraw(&self, _tick: &FrameTick) -> bool {
+        self.drawn.as_ref() != Some(&self.face())
+    }
+
     fn tick(&mut self, canvas: &mut Canvas, _tick: &FrameTick) {
-        let date = Local::now();
+        let face = self.face();
         canvas.clear();
 
-        let times = date.format("%I:%M").to_string();
-        let character_style =
-            U8g2TextStyle::new(fonts::u8g2_font_helvB14_tn, Rgb888::new(255, 255, 255));
+        let [r, g, b] = self.color.map(|c| (c * 255.0).round() as u8);
+        let color = Rgb888::new(r, g, b);
+        let time_font = font::named(&self.font).expect("fonts are validated");
+        let date_font = font::named(&self.date_font).expect("fonts are validated");
+        let mut lines = vec![(&time_font, face.time.as_str())];
+        if !face.date.trim().is_empty() {
+            lines.push((&date_font, face.date.as_str()));
+        }
+        let lines: Vec<_> = lines
+            .into_iter()
+            .filter_map(|(font, text)| Some((font, text, bounds(font, text)?)))
+            .collect();
+
+        // The lines are centered together, in the space above the bar.
+        let bar_rows = if self.seconds_bar {
+            BAR_HEIGHT + BAR_GAP
+        } else {
+            0
+        };
+        let block_height = lines
+            .iter()
+            .map(|(_, _, bounds)| bounds.size.height as i32)
+            .sum::<i32>()
+            + LINE_GAP * (lines.len() as i32 - 1).max(0);
+        let mut top = (canvas.height.saturating_sub(bar_rows) as i32 - block_height) / 2;
+        for (font, text, bounds) in lines {
+            let left = (canvas.width as i32 - bounds.size.width as i32) / 2;
+            let at = Point::new(left, top) - bounds.top_left;
+            draw_line(canvas, font, text, at, face.colons, color);
+            top += bounds.size.height as i32 + LINE_GAP;
+        }
 
-        Text::new(&times, Point::new(9, 22), character_style)
-            .draw(canvas)
-            .unwrap();
+        if face.bar > 0 {
+            let bar = Rectangle::new(
+                Point::new(0, canvas.height.saturating_sub(BAR_HEIGHT) as i32),
+                Size::new(face.bar, BAR_HEIGHT),
+            );
+            bar.into_styled(PrimitiveStyle::with_fill(color))
+                .draw(canvas)
+                .ok();
+        }
+        self.drawn = Some(face);
     }
 }
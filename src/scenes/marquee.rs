@@ -0,0 +1,249 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+};
+
+use embedded_graphics::{pixelcolor::Rgb888, prelude::*};
+use serde::Deserialize;
+use u8g2_fonts::{
+    types::{FontColor, VerticalPosition},
+    FontRenderer,
+};
+
+use crate::{
+    font,
+    frame_tick::FrameTick,
+    scenes::param::{self, ParamKind, ParamSpec, ParamValue},
+    Canvas, Scene,
+};
+
+const MAX_QUEUED: usize = 32;
+const MAX_TEXT_LEN: usize = 1024;
+
+const PARAMS: [ParamSpec; 3] = [
+    ParamSpec {
+        name: "speed",
+        description: "Pixels per second, for messages without their own.",
+        kind: ParamKind::Float {
+            min: 1.0,
+            max: 500.0,
+        },
+        default: ParamValue::Float(24.0),
+    },
+    ParamSpec {
+        name: "color",
+        description: "Color of messages without their own.",
+        kind: ParamKind::Color,
+        default: ParamValue::Color([1.0, 1.0, 1.0]),
+    },
+    ParamSpec {
+        name: "font",
+        description: "Font of messages without their own.",
+        kind: ParamKind::Font,
+        default: ParamValue::Text(Cow::Borrowed("6x10_tf")),
+    },
+];
+
+/// A message for the marquee, from `[[marquee]]` in the config or posted to
+/// the control endpoint. Unset fields follow the scene's parameters.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Message {
+    pub text: String,
+    /// sRGB, each channel in `0.0..=1.0`.
+    pub color: Option<[f32; 3]>,
+    pub font: Option<String>,
+    /// Pixels per second.
+    pub speed: Option<f32>,
+    /// Times it scrolls across before the next message.
+    #[serde(default = "default_repeat")]
+    pub repeat: u32,
+}
+
+fn default_repeat() -> u32 {
+    1
+}
+
+impl Message {
+    pub fn validate(&self) -> Result<(), String> {
+        param::validate_text(
+            "message",
+            &self.text,
+            MAX_TEXT_LEN,
+            self.color,
+            self.font.as_deref(),
+        )?;
+        if let Some(speed) = self.speed {
+            param::validate(&PARAMS, "speed", ParamValue::Float(speed))?;
+        }
+        if self.repeat == 0 {
+            return Err("repeat must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn queue() -> &'static Mutex<VecDeque<Message>> {
+    static QUEUE: OnceLock<Mutex<VecDeque<Message>>> = OnceLock::new();
+    QUEUE.get_or_init(|| Mutex::new(VecDeque::new()))
+}
+
+/// Queues `message` to be shown by the marquee after the current one, ahead
+/// of the configured messages. Returns how many messages are now waiting, or
+/// an error once `MAX_QUEUED` already are.
+pub fn enqueue(message: Message) -> Result<usize, String> {
+    message.validate()?;
+    let mut queue = queue().lock().unwrap();
+    if queue.len() >= MAX_QUEUED {
+        return Err(format!("{MAX_QUEUED} messages already waiting"));
+    }
+    queue.push_back(message);
+    Ok(queue.len())
+}
+
+/// The message being scrolled.
+struct Scroll {
+    text: String,
+    font: FontRenderer,
+    color: Option<[f32; 3]>,
+    speed: Option<f32>,
+    width: f32,
+    // Left edge of the text, moving left from the right edge of the canvas.
+    x: f32,
+    repeats_left: u32,
+}
+
+/// Scrolls messages across the canvas, right to left: queued ones first, then
+/// the configured ones in turn.
+pub struct MarqueeScene {
+    messages: Vec<Message>,
+    next: usize,
+    scroll: Option<Scroll>,
+    speed: f32,
+    color: [f32; 3],
+    font: String,
+    // Whether the canvas was cleared with nothing to show, so it needn't be
+    // again until there is.
+    idle: bool,
+}
+
+impl MarqueeScene {
+    pub fn new(messages: &[Message], _width: u32, _height: u32) -> Result<Self, String> {
+        for message in messages {
+            message
+                .validate()
+                .map_err(|e| format!("marquee message {:?}: {e}", message.text))?;
+        }
+        Ok(MarqueeScene {
+            messages: messages.to_vec(),
+            next: 0,
+            scroll: None,
+            speed: PARAMS[0].default.as_float(),
+            color: PARAMS[1].default.as_color(),
+            font: PARAMS[2].default.as_text().to_string(),
+            idle: false,
+        })
+    }
+
+    fn next_message(&mut self) -> Option<Message> {
+        if let Some(message) = queue().lock().unwrap().pop_front() {
+            return Some(message);
+        }
+        let message = self.messages.get(self.next)?.clone();
+        self.next = (self.next + 1) % self.messages.len();
+        Some(message)
+    }
+
+    fn start(&self, message: Message, canvas_width: u32) -> Scroll {
+        let font = font::checked(message.font.as_deref(), &self.font);
+        let (text, width) = font::line(&font, &message.text);
+
+        Scroll {
+            text,
+            font,
+            color: message.color,
+            speed: message.speed,
+            width: width as f32,
+            x: canvas_width as f32,
+            repeats_left: message.repeat,
+        }
+    }
+}
+
+impl Scene for MarqueeScene {
+    fn name(&self) -> &'static str {
+        "marquee"
+    }
+
+    fn description(&self) -> &'static str {
+        "Scrolling text messages, from the config and the control endpoint."
+    }
+
+    fn params(&self) -> &'static [ParamSpec] {
+        &PARAMS
+    }
+
+    fn get_param(&self, name: &str) -> Option<ParamValue> {
+        match name {
+            "speed" => Some(ParamValue::Float(self.speed)),
+            "color" => Some(ParamValue::Color(self.color)),
+            "font" => Some(ParamValue::Text(self.font.clone().into())),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = param::validate(&PARAMS, name, value)?;
+        match name {
+            "speed" => self.speed = value.as_float(),
+            "color" => self.color = value.as_color(),
+            "font" => self.font = value.as_text().to_string(),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn wants_redraw(&self, _tick: &FrameTick) -> bool {
+        !self.idle || !self.messages.is_empty() || !queue().lock().unwrap().is_empty()
+    }
+
+    fn tick(&mut self, canvas: &mut Canvas, tick: &FrameTick) {
+        canvas.clear();
+        if self.scroll.is_none() {
+            self.scroll = self
+                .next_message()
+                .map(|message| self.start(message, canvas.width));
+        }
+        let Some(scroll) = &mut self.scroll else {
+            self.idle = true;
+            return;
+        };
+        self.idle = false;
+
+        let [r, g, b] = scroll
+            .color
+            .unwrap_or(self.color)
+            .map(|c| (c * 255.0).round() as u8);
+        let position = Point::new(scroll.x.round() as i32, canvas.height as i32 / 2);
+        scroll
+            .font
+            .render(
+                scroll.text.as_str(),
+                position,
+                VerticalPosition::Center,
+                FontColor::Transparent(Rgb888::new(r, g, b)),
+                canvas,
+            )
+            .ok();
+
+        scroll.x -= scroll.speed.unwrap_or(self.speed) * tick.dt;
+        if scroll.x + scroll.width < 0.0 {
+            scroll.repeats_left -= 1;
+            scroll.x = canvas.width as f32;
+            if scroll.repeats_left == 0 {
+                self.scroll = None;
+            }
+        }
+    }
+}
@@ -1,9 +1,213 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    sync::{Mutex, OnceLock},
+};
+
+use log2::*;
+
+use crate::{config::Config, frame_tick::FrameTick, Canvas};
+
 pub mod clock;
+pub mod marquee;
+pub mod param;
 pub mod plasma;
+pub mod plugin;
 pub mod sand;
+pub mod script;
+pub mod shader;
+pub mod test_pattern;
 pub mod wave;
 
 pub use self::clock::ClockScene;
+pub use self::marquee::MarqueeScene;
+pub use self::param::{ParamKind, ParamSpec, ParamValue};
 pub use self::plasma::PlasmaScene;
+pub use self::plugin::{PluginConfig, PluginScene};
 pub use self::sand::SandScene;
+pub use self::script::{ScriptConfig, ScriptScene};
+pub use self::shader::ShaderScene;
+pub use self::test_pattern::TestPatternScene;
 pub use self::wave::WaveScene;
+
+/// Builds the scene called `name`: a built-in one, or a script, shader or
+/// plugin from `config`. These can't take the name of a built-in scene.
+pub fn build(
+    name: &str,
+    config: &Config,
+    width: u32,
+    height: u32,
+) -> Result<Box<dyn Scene>, String> {
+    Ok(match name {
+        "wave" => Box::new(WaveScene::new(width, height)),
+        "plasma" => Box::new(PlasmaScene::new(width, height)),
+        "sand" => Box::new(SandScene::new(width, height)),
+        "clock" => Box::new(ClockScene::new(width, height)),
+        "marquee" => Box::new(MarqueeScene::new(&config.marquee, width, height)?),
+        "test_pattern" => Box::new(TestPatternScene::new(width, height)),
+        _ => {
+            if let Some(script) = config.scripts.get(name) {
+                Box::new(ScriptScene::new(name, script, width, height))
+            } else if let Some(source) = config.shaders.get(name) {
+                Box::new(ShaderScene::new(name, source, width, height)?)
+            } else if let Some(plugin) = config.plugins.get(name) {
+                Box::new(PluginScene::new(name, plugin, width, height)?)
+            } else {
+                return Err(format!("unknown scene {name}"));
+            }
+        }
+    })
+}
+
+/// `text` as a `&'static str`, for the names scenes from the config return
+/// from `Scene::name` and `Scene::params`. Each distinct text is leaked once
+/// and shared after, so a scene `SceneRunner` rebuilds again and again after
+/// failures doesn't leak more each time.
+pub(crate) fn intern(text: &str) -> &'static str {
+    static INTERNED: OnceLock<Mutex<BTreeSet<&'static str>>> = OnceLock::new();
+    let mut interned = INTERNED.get_or_init(Default::default).lock().unwrap();
+    if let Some(text) = interned.get(text) {
+        return text;
+    }
+    let text: &'static str = Box::leak(text.to_string().into_boxed_str());
+    interned.insert(text);
+    text
+}
+
+/// Logs the errors of a script or plugin, each only when it differs from the
+/// last, so one that fails on every frame doesn't log every frame.
+pub(crate) struct ErrorLog {
+    kind: &'static str,
+    name: &'static str,
+    last: Option<String>,
+}
+
+impl ErrorLog {
+    pub fn new(kind: &'static str, name: &'static str) -> ErrorLog {
+        ErrorLog {
+            kind,
+            name,
+            last: None,
+        }
+    }
+
+    pub fn report(&mut self, error: String) {
+        if self.last.as_ref() != Some(&error) {
+            error!("{} {}: {}", self.kind, self.name, error);
+            self.last = Some(error);
+        }
+    }
+
+    /// Forgets the last error, so it's logged again if it comes back.
+    pub fn clear(&mut self) {
+        self.last = None;
+    }
+
+    #[cfg(test)]
+    pub fn last(&self) -> Option<&str> {
+        self.last.as_deref()
+    }
+}
+
+/// `error` from a script or plugin, or that it was stopped after its `limit`
+/// when `stopped`.
+pub(crate) fn describe(
+    error: impl fmt::Display,
+    stopped: bool,
+    limit: impl fmt::Display,
+) -> String {
+    if stopped {
+        format!("stopped after its {limit}")
+    } else {
+        error.to_string()
+    }
+}
+
+/// How often a scene wants to be updated, independently of the output fps.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UpdateRate {
+    /// `tick` on every output frame.
+    EveryFrame,
+    /// `tick` at most this many times per second. Frames in between re-use
+    /// the last rendered canvas.
+    Limited(f32),
+    /// `step` the simulation this many times per second, then `draw` once per
+    /// output frame with the interpolation factor between the last two steps.
+    FixedStep(f32),
+}
+
+/// A visual that renders into a canvas.
+///
+/// Every scene is built with `new(width, height)` for the size of the canvas it
+/// will render to, and driven by a `SceneRunner`.
+pub trait Scene {
+    /// A short, unique, lowercase name, as used in the config.
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// Called before the first update after the scene becomes visible.
+    fn on_enter(&mut self) {}
+
+    /// Called when the scene stops being shown.
+    fn on_exit(&mut self) {}
+
+    /// The canvas changed size. Scenes that keep per-pixel state resize it.
+    fn resize(&mut self, _width: u32, _height: u32) {}
+
+    /// The knobs `get_param` and `set_param` accept.
+    fn params(&self) -> &'static [ParamSpec] {
+        &[]
+    }
+
+    fn get_param(&self, _name: &str) -> Option<ParamValue> {
+        None
+    }
+
+    /// Sets a parameter listed by `params`. Fails for unknown names and for
+    /// values of the wrong type or out of range.
+    fn set_param(&mut self, name: &str, _value: ParamValue) -> Result<(), String> {
+        Err(format!("unknown parameter {name}"))
+    }
+
+    fn tick(&mut self, _canvas: &mut Canvas, _tick: &FrameTick) {}
+
+    fn update_rate(&self) -> UpdateRate {
+        UpdateRate::EveryFrame
+    }
+
+    /// Whether the scene would draw something different at `tick` than it
+    /// last did. When it wouldn't, the update is skipped and the last canvas
+    /// is kept.
+    fn wants_redraw(&self, _tick: &FrameTick) -> bool {
+        true
+    }
+
+    /// Advances a `UpdateRate::FixedStep` simulation by `dt` seconds.
+    fn step(&mut self, _dt: f32) {}
+
+    /// Draws a `UpdateRate::FixedStep` simulation. `alpha` is in `0.0..1.0`,
+    /// how far the output frame falls between the previous and current step.
+    fn draw(&mut self, _canvas: &mut Canvas, _tick: &FrameTick, _alpha: f32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interned_text_is_shared() {
+        let first = intern("interned");
+        let again = intern(&String::from("interned"));
+        assert_eq!(first, "interned");
+        assert!(std::ptr::eq(first, again));
+        assert!(!std::ptr::eq(first, intern("other")));
+    }
+
+    #[test]
+    fn rebuilt_scenes_share_their_names() {
+        let first = ShaderScene::new("rebuilt", "x", 4, 4).unwrap();
+        let again = ShaderScene::new("rebuilt", "x", 4, 4).unwrap();
+        assert!(std::ptr::eq(first.name(), again.name()));
+    }
+}
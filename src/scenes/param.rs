@@ -0,0 +1,232 @@
+use std::{borrow::Cow, fmt, sync::Arc};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    font,
+    gradient::{self, Gradient},
+};
+
+/// The type and valid range of a scene parameter.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParamKind {
+    Float {
+        min: f32,
+        max: f32,
+    },
+    Int {
+        min: i64,
+        max: i64,
+    },
+    Bool,
+    /// sRGB, each channel in `0.0..=1.0`.
+    Color,
+    /// The name of a built-in or configured palette, or empty for the scene's
+    /// own colors.
+    Palette,
+    /// The name of a u8g2 font, as listed by `font::names`.
+    Font,
+    /// A strftime format, like `%H:%M`.
+    TimeFormat,
+    /// An IANA timezone name, like `Europe/Berlin`, or empty for the system's.
+    Timezone,
+}
+
+/// A parameter value. In the config, written as a TOML boolean, integer,
+/// float, `[r, g, b]` array or string.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    Color([f32; 3]),
+    Text(Cow<'static, str>),
+}
+
+impl ParamValue {
+    pub fn as_float(&self) -> f32 {
+        match *self {
+            ParamValue::Float(v) => v,
+            ParamValue::Int(v) => v as f32,
+            ParamValue::Bool(v) => v as u8 as f32,
+            ParamValue::Color([r, g, b]) => (r + g + b) / 3.0,
+            ParamValue::Text(_) => 0.0,
+        }
+    }
+
+    pub fn as_int(&self) -> i64 {
+        match *self {
+            ParamValue::Int(v) => v,
+            ref other => other.as_float().round() as i64,
+        }
+    }
+
+    pub fn as_bool(&self) -> bool {
+        match *self {
+            ParamValue::Bool(v) => v,
+            ref other => other.as_float() != 0.0,
+        }
+    }
+
+    pub fn as_color(&self) -> [f32; 3] {
+        match *self {
+            ParamValue::Color(v) => v,
+            ref other => [other.as_float(); 3],
+        }
+    }
+
+    pub fn as_text(&self) -> &str {
+        match self {
+            ParamValue::Text(v) => v,
+            _ => "",
+        }
+    }
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamValue::Float(v) => write!(f, "{v}"),
+            ParamValue::Int(v) => write!(f, "{v}"),
+            ParamValue::Bool(v) => write!(f, "{v}"),
+            ParamValue::Color([r, g, b]) => write!(f, "{r} {g} {b}"),
+            ParamValue::Text(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+/// One knob a scene exposes, as listed by `Scene::params`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub kind: ParamKind,
+    pub default: ParamValue,
+}
+
+impl ParamSpec {
+    /// Parses a value written as text, like `2.5`, `true`, `0 0.5 1`, `fire`,
+    /// `6x10_tf` or `%H:%M`.
+    pub fn parse(&self, text: &str) -> Result<ParamValue, String> {
+        let text = text.trim();
+        let invalid = || format!("invalid value for {}: {text:?}", self.name);
+        let value = match self.kind {
+            ParamKind::Float { .. } => ParamValue::Float(text.parse().map_err(|_| invalid())?),
+            ParamKind::Int { .. } => ParamValue::Int(text.parse().map_err(|_| invalid())?),
+            ParamKind::Bool => ParamValue::Bool(text.parse().map_err(|_| invalid())?),
+            ParamKind::Color => {
+                let channels = text
+                    .split(|c: char| c.is_whitespace() || c == ',')
+                    .filter(|part| !part.is_empty())
+                    .map(str::parse)
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map_err(|_| invalid())?;
+                ParamValue::Color(channels.try_into().map_err(|_| invalid())?)
+            }
+            ParamKind::Palette | ParamKind::Font | ParamKind::TimeFormat | ParamKind::Timezone => {
+                ParamValue::Text(text.to_string().into())
+            }
+        };
+        self.validate(value)
+    }
+
+    /// Checks that `value` has this parameter's type and falls in its range.
+    /// Integers are accepted for float parameters.
+    pub fn validate(&self, value: ParamValue) -> Result<ParamValue, String> {
+        let value = match (self.kind, value) {
+            (ParamKind::Float { .. }, ParamValue::Int(v)) => ParamValue::Float(v as f32),
+            (_, value) => value,
+        };
+        let in_range = match (self.kind, &value) {
+            (ParamKind::Float { min, max }, ParamValue::Float(v)) => (min..=max).contains(v),
+            (ParamKind::Int { min, max }, ParamValue::Int(v)) => (min..=max).contains(v),
+            (ParamKind::Bool, ParamValue::Bool(_)) => true,
+            (ParamKind::Color, ParamValue::Color(rgb)) => {
+                rgb.iter().all(|c| (0.0..=1.0).contains(c))
+            }
+            (ParamKind::Palette, ParamValue::Text(name)) => {
+                if !name.is_empty() && gradient::named(name).is_none() {
+                    return Err(format!("{}: unknown palette {name}", self.name));
+                }
+                true
+            }
+            (ParamKind::Font, ParamValue::Text(name)) => {
+                if font::named(name).is_none() {
+                    return Err(format!("{}: unknown font {name}", self.name));
+                }
+                true
+            }
+            (ParamKind::TimeFormat, ParamValue::Text(format)) => {
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(format!("{}: invalid time format {format:?}", self.name));
+                }
+                true
+            }
+            (ParamKind::Timezone, ParamValue::Text(name)) => {
+                if !name.is_empty() && name.parse::<Tz>().is_err() {
+                    return Err(format!("{}: unknown timezone {name}", self.name));
+                }
+                true
+            }
+            _ => {
+                return Err(format!(
+                    "{} expects {:?}, got {:?}",
+                    self.name, self.kind, value
+                ))
+            }
+        };
+
+        if in_range {
+            Ok(value)
+        } else {
+            Err(format!(
+                "{} is out of range for {:?}: {value}",
+                self.name, self.kind
+            ))
+        }
+    }
+}
+
+/// Finds `name` in `params` and validates `value` against it, for
+/// `Scene::set_param` implementations.
+pub fn validate(params: &[ParamSpec], name: &str, value: ParamValue) -> Result<ParamValue, String> {
+    params
+        .iter()
+        .find(|spec| spec.name == name)
+        .ok_or_else(|| format!("unknown parameter {name}"))?
+        .validate(value)
+}
+
+/// Checks text posted to be shown, called `what` in errors: that it isn't
+/// blank or longer than `max_len` bytes, and that its color and font are
+/// valid when set.
+pub fn validate_text(
+    what: &str,
+    text: &str,
+    max_len: usize,
+    color: Option<[f32; 3]>,
+    font: Option<&str>,
+) -> Result<(), String> {
+    if text.trim().is_empty() {
+        return Err(format!("empty {what}"));
+    }
+    if text.len() > max_len {
+        return Err(format!("{what} longer than {max_len} bytes"));
+    }
+    if color.is_some_and(|rgb| !rgb.iter().all(|c| (0.0..=1.0).contains(c))) {
+        return Err("color channels must be between 0 and 1".to_string());
+    }
+    if let Some(font) = font.filter(|font| font::named(font).is_none()) {
+        return Err(format!("unknown font {font}"));
+    }
+    Ok(())
+}
+
+/// The value of a `ParamKind::Palette` parameter set to `palette`.
+pub fn palette_value(palette: Option<&Arc<Gradient>>) -> ParamValue {
+    ParamValue::Text(palette.map_or("", |p| p.name()).to_string().into())
+}
@@ -1,25 +1,119 @@
-use crate::{Canvas, frame_tick::FrameTick, Scene};
+use std::{borrow::Cow, sync::Arc};
+
+use crate::{
+    frame_tick::FrameTick,
+    gradient::{self, Gradient},
+    scenes::param::{self, ParamKind, ParamSpec, ParamValue},
+    Canvas, Scene,
+};
+
+// The pattern was made for a 64 pixel wide panel, on which a pixel is 1/128
+// of a unit of the pattern.
+const REFERENCE_SIZE: f32 = 64.0;
+const REFERENCE_SCALE: f32 = 128.0;
+
+const PARAMS: [ParamSpec; 4] = [
+    ParamSpec {
+        name: "speed",
+        description: "How fast the pattern moves.",
+        kind: ParamKind::Float {
+            min: 0.0,
+            max: 10.0,
+        },
+        default: ParamValue::Float(1.0),
+    },
+    ParamSpec {
+        name: "zoom",
+        description: "How much of the pattern fits on the canvas.",
+        kind: ParamKind::Float {
+            min: 0.5,
+            max: 50.0,
+        },
+        default: ParamValue::Float(5.0),
+    },
+    ParamSpec {
+        name: "color_frequency",
+        description: "How many color bands each plasma swirl has.",
+        kind: ParamKind::Float {
+            min: 0.0,
+            max: 50.0,
+        },
+        default: ParamValue::Float(9.0),
+    },
+    ParamSpec {
+        name: "palette",
+        description: "Colors the plasma is drawn in, instead of its own red and green swirls.",
+        kind: ParamKind::Palette,
+        default: ParamValue::Text(Cow::Borrowed("")),
+    },
+];
 
 pub struct PlasmaScene {
     speed: f32,
+    zoom: f32,
+    color_frequency: f32,
+    palette: Option<Arc<Gradient>>,
 }
 
 impl PlasmaScene {
-    pub fn new(speed: f32) -> Self {
-        PlasmaScene { speed }
+    pub fn new(_width: u32, _height: u32) -> Self {
+        PlasmaScene {
+            speed: PARAMS[0].default.as_float(),
+            zoom: PARAMS[1].default.as_float(),
+            color_frequency: PARAMS[2].default.as_float(),
+            palette: gradient::named(PARAMS[3].default.as_text()),
+        }
     }
 }
 
 impl Scene for PlasmaScene {
+    fn name(&self) -> &'static str {
+        "plasma"
+    }
+
+    fn description(&self) -> &'static str {
+        "A classic sine plasma."
+    }
+
+    fn params(&self) -> &'static [ParamSpec] {
+        &PARAMS
+    }
+
+    fn get_param(&self, name: &str) -> Option<ParamValue> {
+        match name {
+            "speed" => Some(ParamValue::Float(self.speed)),
+            "zoom" => Some(ParamValue::Float(self.zoom)),
+            "color_frequency" => Some(ParamValue::Float(self.color_frequency)),
+            "palette" => Some(param::palette_value(self.palette.as_ref())),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = param::validate(&PARAMS, name, value)?;
+        match name {
+            "speed" => self.speed = value.as_float(),
+            "zoom" => self.zoom = value.as_float(),
+            "color_frequency" => self.color_frequency = value.as_float(),
+            "palette" => self.palette = gradient::named(value.as_text()),
+            _ => {}
+        }
+        Ok(())
+    }
+
     fn tick(&mut self, canvas: &mut Canvas, tick: &FrameTick) {
         let t = tick.start.elapsed().as_secs_f32() * 0.5f32 * self.speed;
 
+        // Scaled with the longer side, so the pattern keeps its aspect and
+        // covers the same area at any resolution, as it did on a 64x32 panel.
+        let size = canvas.width.max(canvas.height) as f32 * REFERENCE_SCALE / REFERENCE_SIZE;
+
         for y in 0..canvas.height {
             for x in 0..canvas.width {
-                let xp =
-                    ((x as f32 / 128.0) - 0.5) * (5.0 + (t * 0.25).sin()) + (t * 0.25).sin() * 5.0;
-                let yp =
-                    ((y as f32 / 128.0) - 0.5) * (5.0 + (t * 0.25).sin()) + (t * 0.25).cos() * 5.0;
+                let xp = ((x as f32 / size) - 0.5) * (self.zoom + (t * 0.25).sin())
+                    + (t * 0.25).sin() * 5.0;
+                let yp = ((y as f32 / size) - 0.5) * (self.zoom + (t * 0.25).sin())
+                    + (t * 0.25).cos() * 5.0;
 
                 let pixel = (((0.25 * t).sin() * xp + (0.29 * t).cos() * yp + t).sin()
                     + (((xp + (t * 0.25).sin() * 4.0).powf(2.0)
@@ -34,10 +128,16 @@ impl Scene for PlasmaScene {
                         .cos())
                 .sin();
 
-                let u = ((9.0 * pixel + 0.5 * xp + t).cos() * 0.5 + 0.5).powf(2.0);
-                let v = ((9.0 * pixel + 0.5 * yp + t).sin() * 0.5 + 0.5).powf(2.0);
+                let u = ((self.color_frequency * pixel + 0.5 * xp + t).cos() * 0.5 + 0.5).powf(2.0);
+                let v = ((self.color_frequency * pixel + 0.5 * yp + t).sin() * 0.5 + 0.5).powf(2.0);
 
-                canvas.set_pixel(x, y, u, v, (u + v) / 2.0)
+                match &self.palette {
+                    Some(palette) => {
+                        let [r, g, b] = palette.sample((u + v) / 2.0);
+                        canvas.set(x, y, [r, g, b, 1.0]);
+                    }
+                    None => canvas.set_pixel(x, y, u, v, (u + v) / 2.0),
+                }
             }
         }
     }
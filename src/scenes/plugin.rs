@@ -0,0 +1,471 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    ops::Range,
+    sync::{Mutex, OnceLock},
+};
+
+use log2::*;
+use serde::Deserialize;
+use wasmi::{
+    core::TrapCode, Caller, Engine, Extern, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, TypedFunc,
+};
+
+use crate::{
+    color::decode_srgb,
+    frame_tick::FrameTick,
+    scenes::{
+        self,
+        param::{self, ParamKind, ParamSpec, ParamValue},
+    },
+    Canvas, Scene,
+};
+
+// Longest message a plugin can log at once.
+const MAX_LOG_LEN: usize = 256;
+
+/// A WebAssembly scene, listed under `[plugins]` in the config.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    pub path: String,
+    /// Instructions, roughly, the plugin may run per frame before it's
+    /// stopped.
+    #[serde(default = "default_fuel")]
+    pub fuel: u64,
+    /// The most memory the plugin may grow to.
+    #[serde(default = "default_memory_mb")]
+    pub memory_mb: usize,
+    /// Numeric parameters the plugin reads with `param`, by name.
+    #[serde(default)]
+    pub params: BTreeMap<String, PluginParam>,
+}
+
+fn default_fuel() -> u64 {
+    2_000_000
+}
+
+fn default_memory_mb() -> usize {
+    16
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginParam {
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// What host functions can reach, kept in the plugin's store.
+struct Host {
+    name: &'static str,
+    limits: StoreLimits,
+    params: Vec<(&'static str, f32)>,
+}
+
+/// A scene compiled to WebAssembly, run in an interpreter with no access to
+/// anything but the host functions below, a memory limit and a per-frame fuel
+/// limit, so the same `.wasm` file runs on every architecture and a stuck
+/// plugin can't stall the frame loop.
+///
+/// The module exports its `memory` and:
+///
+/// - `frame_buffer(width: i32, height: i32) -> i32`, the address in its memory
+///   of `width * height` RGBA pixels, a byte per channel in sRGB, row by row.
+///   It's called again when the canvas changes size.
+/// - `tick(t: f32, dt: f32, width: i32, height: i32)`, which draws the frame
+///   into that buffer. The buffer keeps what was drawn on the previous frame.
+///
+/// It can import from `env`:
+///
+/// - `param(name: i32, len: i32) -> f32`, the value of the parameter whose
+///   UTF-8 name is at `name`, or 0 for unknown ones.
+/// - `log(message: i32, len: i32)`, which logs a UTF-8 message.
+pub struct PluginScene {
+    name: &'static str,
+    params: &'static [ParamSpec],
+    fuel: u64,
+    store: Store<Host>,
+    memory: Memory,
+    frame_buffer: TypedFunc<(i32, i32), i32>,
+    tick: TypedFunc<(f32, f32, i32, i32), ()>,
+    // Where the pixels are in the plugin's memory, and the size they're for.
+    buffer: Option<(usize, u32, u32)>,
+    errors: scenes::ErrorLog,
+}
+
+impl PluginScene {
+    /// Loads and instantiates the plugin.
+    pub fn new(name: &str, config: &PluginConfig, width: u32, height: u32) -> Result<Self, String> {
+        let name = scenes::intern(name);
+        check_params(config).map_err(|e| format!("plugin {name}: {e}"))?;
+        let params = param_specs(name, config);
+
+        let mut scene =
+            Self::load(name, params, config).map_err(|e| format!("plugin {name}: {e}"))?;
+        scene
+            .buffer(width, height)
+            .map_err(|e| format!("plugin {name}: {e}"))?;
+        Ok(scene)
+    }
+
+    fn load(
+        name: &'static str,
+        params: &'static [ParamSpec],
+        config: &PluginConfig,
+    ) -> Result<Self, String> {
+        let wasm =
+            fs::read(&config.path).map_err(|e| format!("failed to read {}: {e}", config.path))?;
+        let mut engine_config = wasmi::Config::default();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config);
+        let module = Module::new(&engine, &wasm).map_err(|e| e.to_string())?;
+
+        let host = Host {
+            name,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(config.memory_mb * 1024 * 1024)
+                .instances(1)
+                .memories(1)
+                .tables(1)
+                .build(),
+            params: params
+                .iter()
+                .map(|spec| (spec.name, spec.default.as_float()))
+                .collect(),
+        };
+        let mut store = Store::new(&engine, host);
+        store.limiter(|host| &mut host.limits);
+
+        let mut linker = Linker::new(&engine);
+        linker
+            .func_wrap(
+                "env",
+                "param",
+                |caller: Caller<Host>, ptr: i32, len: i32| {
+                    let name = read_string(&caller, ptr, len).unwrap_or_default();
+                    caller
+                        .data()
+                        .params
+                        .iter()
+                        .find(|(param, _)| *param == name)
+                        .map_or(0.0, |(_, value)| *value)
+                },
+            )
+            .map_err(|e| e.to_string())?;
+        linker
+            .func_wrap("env", "log", |caller: Caller<Host>, ptr: i32, len: i32| {
+                if let Some(message) = read_string(&caller, ptr, len.min(MAX_LOG_LEN as i32)) {
+                    info!("Plugin {}: {}", caller.data().name, message);
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        // Start functions get the same fuel as a frame.
+        store.set_fuel(config.fuel).map_err(|e| e.to_string())?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| describe(&e, config.fuel))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("no exported memory")?;
+        let frame_buffer = instance
+            .get_typed_func(&store, "frame_buffer")
+            .map_err(|e| format!("frame_buffer: {e}"))?;
+        let tick = instance
+            .get_typed_func(&store, "tick")
+            .map_err(|e| format!("tick: {e}"))?;
+
+        Ok(PluginScene {
+            name,
+            params,
+            fuel: config.fuel,
+            store,
+            memory,
+            frame_buffer,
+            tick,
+            buffer: None,
+            errors: scenes::ErrorLog::new("Plugin", name),
+        })
+    }
+
+    /// The address of the plugin's frame buffer, asking for it again if the
+    /// size changed.
+    fn buffer(&mut self, width: u32, height: u32) -> Result<usize, String> {
+        if let Some((ptr, w, h)) = self.buffer {
+            if (w, h) == (width, height) {
+                return Ok(ptr);
+            }
+        }
+        self.buffer = None;
+
+        self.store.set_fuel(self.fuel).map_err(|e| e.to_string())?;
+        let ptr = self
+            .frame_buffer
+            .call(&mut self.store, (width as i32, height as i32))
+            .map_err(|e| format!("frame_buffer: {}", describe(&e, self.fuel)))?;
+        let len = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(4));
+        let ptr = len
+            .and_then(|len| in_bounds(ptr, len, self.memory.data(&self.store).len()))
+            .ok_or_else(|| format!("frame buffer at {} is out of bounds", ptr as u32))?
+            .start;
+        self.buffer = Some((ptr, width, height));
+        Ok(ptr)
+    }
+}
+
+fn check_params(config: &PluginConfig) -> Result<(), String> {
+    for (name, param) in &config.params {
+        let PluginParam {
+            min, max, default, ..
+        } = *param;
+        if ![min, max, default].iter().all(|v| v.is_finite()) {
+            return Err(format!("param {name}: min, max and default must be finite"));
+        }
+        if !(min <= default && default <= max) {
+            return Err(format!(
+                "param {name}: default {default} isn't between min {min} and max {max}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The plugin's parameters, made once per plugin and shared by every scene
+/// built from it, like the names in `scenes::intern`.
+fn param_specs(name: &'static str, config: &PluginConfig) -> &'static [ParamSpec] {
+    static SPECS: OnceLock<Mutex<BTreeMap<&'static str, &'static [ParamSpec]>>> = OnceLock::new();
+    let mut specs = SPECS.get_or_init(Default::default).lock().unwrap();
+    specs.entry(name).or_insert_with(|| {
+        config
+            .params
+            .iter()
+            .map(|(param, spec)| ParamSpec {
+                name: scenes::intern(param),
+                description: scenes::intern(&spec.description),
+                kind: ParamKind::Float {
+                    min: spec.min,
+                    max: spec.max,
+                },
+                default: ParamValue::Float(spec.default),
+            })
+            .collect::<Vec<_>>()
+            .leak()
+    })
+}
+
+fn read_string(caller: &Caller<Host>, ptr: i32, len: i32) -> Option<String> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let data = memory.data(caller);
+    let bytes = &data[in_bounds(ptr, len.max(0) as usize, data.len())?];
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// The range of `len` bytes at the wasm address `ptr`, if it fits in a
+/// memory of `size` bytes.
+fn in_bounds(ptr: i32, len: usize, size: usize) -> Option<Range<usize>> {
+    let start = ptr as u32 as usize;
+    let end = start.checked_add(len)?;
+    (end <= size).then_some(start..end)
+}
+
+fn describe(error: &wasmi::Error, fuel: u64) -> String {
+    let stopped = error.as_trap_code() == Some(TrapCode::OutOfFuel);
+    scenes::describe(error, stopped, format_args!("{fuel} fuel"))
+}
+
+impl Scene for PluginScene {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        "A WebAssembly plugin."
+    }
+
+    fn params(&self) -> &'static [ParamSpec] {
+        self.params
+    }
+
+    fn get_param(&self, name: &str) -> Option<ParamValue> {
+        self.store
+            .data()
+            .params
+            .iter()
+            .find(|(param, _)| *param == name)
+            .map(|(_, value)| ParamValue::Float(*value))
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = param::validate(self.params, name, value)?.as_float();
+        for (param, current) in &mut self.store.data_mut().params {
+            if *param == name {
+                *current = value;
+            }
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, canvas: &mut Canvas, tick: &FrameTick) {
+        let ptr = match self.buffer(canvas.width, canvas.height) {
+            Ok(ptr) => ptr,
+            Err(e) => return self.errors.report(e),
+        };
+
+        self.store.set_fuel(self.fuel).ok();
+        let args = (tick.t, tick.dt, canvas.width as i32, canvas.height as i32);
+        if let Err(e) = self.tick.call(&mut self.store, args) {
+            // Whatever was drawn before the trap is still shown.
+            let error = describe(&e, self.fuel);
+            self.errors.report(error);
+        }
+
+        // The buffer was in bounds when the plugin handed it over, and wasm
+        // memory can't shrink.
+        let len = canvas.pixels.len() * 4;
+        let pixels = &self.memory.data(&self.store)[ptr..ptr + len];
+        for (pixel, rgba) in canvas.pixels.iter_mut().zip(pixels.chunks_exact(4)) {
+            *pixel = [
+                decode_srgb(rgba[0] as f32 / 255.0),
+                decode_srgb(rgba[1] as f32 / 255.0),
+                decode_srgb(rgba[2] as f32 / 255.0),
+                rgba[3] as f32 / 255.0,
+            ];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(min: f32, max: f32, default: f32) -> PluginConfig {
+        PluginConfig {
+            path: "missing.wasm".to_string(),
+            fuel: default_fuel(),
+            memory_mb: default_memory_mb(),
+            params: BTreeMap::from([(
+                "speed".to_string(),
+                PluginParam {
+                    min,
+                    max,
+                    default,
+                    description: String::new(),
+                },
+            )]),
+        }
+    }
+
+    /// Builds a plugin from WebAssembly text, written to a file of its own.
+    fn plugin(name: &str, source: &str, memory_mb: usize) -> Result<PluginScene, String> {
+        let path =
+            std::env::temp_dir().join(format!("matryx-plugin-{}-{name}.wasm", std::process::id()));
+        fs::write(&path, wat::parse_str(source).unwrap()).unwrap();
+        let config = PluginConfig {
+            path: path.to_str().unwrap().to_string(),
+            fuel: 1_000_000,
+            memory_mb,
+            params: BTreeMap::new(),
+        };
+        let scene = PluginScene::new(name, &config, 4, 4);
+        fs::remove_file(&path).ok();
+        scene
+    }
+
+    /// A module with a frame buffer at `buffer` and `tick` as given.
+    fn module(buffer: i64, tick: &str) -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "frame_buffer") (param i32 i32) (result i32)
+                    (i32.const {buffer}))
+                (func (export "tick") (param f32 f32 i32 i32)
+                    {tick}))"#
+        )
+    }
+
+    fn tick(scene: &mut PluginScene) -> Canvas {
+        let mut canvas = Canvas::new(4, 4);
+        scene.tick(&mut canvas, &FrameTick::at(0.0));
+        canvas
+    }
+
+    #[test]
+    fn the_example_plugin_draws() {
+        let source = fs::read_to_string("plugins/stripes.wat").unwrap();
+        let mut scene = plugin("stripes", &source, 1).unwrap();
+        let canvas = tick(&mut scene);
+        assert!(canvas.pixels.iter().any(|pixel| pixel[..3] != [0.0; 3]));
+        assert_eq!(scene.errors.last(), None);
+    }
+
+    #[test]
+    fn runaway_plugins_are_stopped_and_keep_what_they_drew() {
+        // Draws the first pixel red, then never returns.
+        let source = module(
+            0,
+            "(i32.store (i32.const 0) (i32.const 0xff0000ff))
+             (loop $forever (br $forever))",
+        );
+        let mut scene = plugin("runaway", &source, 1).unwrap();
+        let canvas = tick(&mut scene);
+        assert_eq!(canvas.pixels[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(canvas.pixels[1], [0.0; 4]);
+        let error = scene.errors.last().unwrap();
+        assert!(error.contains("stopped after its 1000000 fuel"), "{error}");
+    }
+
+    #[test]
+    fn memory_cant_grow_past_the_limit() {
+        // Stores what growing by 2 MiB returns in the first pixel: the old
+        // size in pages, or -1 when it's refused.
+        let source = module(0, "(i32.store (i32.const 0) (memory.grow (i32.const 32)))");
+        let refused = tick(&mut plugin("small", &source, 1).unwrap());
+        assert_eq!(refused.pixels[0], [1.0; 4]);
+        let mut scene = plugin("large", &source, 16).unwrap();
+        let grown = tick(&mut scene);
+        assert_eq!(scene.errors.last(), None);
+        assert_eq!(grown.pixels[0], [decode_srgb(1.0 / 255.0), 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn frame_buffers_out_of_bounds_are_errors() {
+        for buffer in [65536 - 63, -1, -64] {
+            let error = plugin("outside", &module(buffer, ""), 1).err().unwrap();
+            assert!(error.contains("out of bounds"), "{error}");
+        }
+        assert!(plugin("inside", &module(65536 - 64, ""), 1).is_ok());
+    }
+
+    #[test]
+    fn invalid_params_are_rejected_by_name() {
+        for (min, max, default) in [
+            (0.0, 1.0, 2.0),
+            (1.0, 0.0, 0.5),
+            (f32::NAN, 1.0, 0.5),
+            (0.0, f32::INFINITY, 0.5),
+        ] {
+            let error = PluginScene::new("bad", &config(min, max, default), 4, 4)
+                .err()
+                .unwrap();
+            assert!(error.contains("param speed"), "{error}");
+        }
+        assert!(check_params(&config(0.0, 1.0, 1.0)).is_ok());
+    }
+
+    #[test]
+    fn bounds_checks_dont_overflow() {
+        assert_eq!(in_bounds(16, 16, 32), Some(16..32));
+        assert_eq!(in_bounds(16, 17, 32), None);
+        assert_eq!(in_bounds(-1, 1, 1024), None);
+        assert_eq!(in_bounds(-1, usize::MAX, usize::MAX), None);
+        assert_eq!(in_bounds(0, usize::MAX, usize::MAX), Some(0..usize::MAX));
+    }
+}
@@ -1,6 +1,14 @@
+use std::{borrow::Cow, sync::Arc};
+
+use log2::*;
 use rand::{prelude::SliceRandom, Rng};
 
-use crate::{Canvas, frame_tick::FrameTick, Scene};
+use crate::{
+    frame_tick::FrameTick,
+    gradient::{self, Gradient},
+    scenes::param::{self, ParamKind, ParamSpec, ParamValue},
+    Canvas, Scene, UpdateRate,
+};
 
 #[derive(Copy, Clone, PartialEq)]
 struct Tile {
@@ -21,6 +29,38 @@ enum TileType {
 
 type Map = Vec<Vec<Tile>>;
 
+const STEP_RATE: f32 = 30.0;
+
+const PARAMS: [ParamSpec; 4] = [
+    ParamSpec {
+        name: "spout_period",
+        description: "Seconds between spout starts. The spout runs for half of each.",
+        kind: ParamKind::Float {
+            min: 0.1,
+            max: 60.0,
+        },
+        default: ParamValue::Float(2.0),
+    },
+    ParamSpec {
+        name: "spout_grains",
+        description: "Grains dropped per step while the spout runs.",
+        kind: ParamKind::Int { min: 0, max: 64 },
+        default: ParamValue::Int(5),
+    },
+    ParamSpec {
+        name: "color",
+        description: "Color of the sand.",
+        kind: ParamKind::Color,
+        default: ParamValue::Color([0.0, 0.9, 0.7]),
+    },
+    ParamSpec {
+        name: "palette",
+        description: "Colors the sand by depth, top to bottom, instead of using color.",
+        kind: ParamKind::Palette,
+        default: ParamValue::Text(Cow::Borrowed("")),
+    },
+];
+
 trait MapTiles<T> {
     fn get_tile(&self, x: T, y: T) -> Option<Tile>;
     fn set_tile(&mut self, x: T, y: T, tile: Tile);
@@ -42,7 +82,7 @@ impl MapTiles<i32> for Map {
     }
 
     fn in_bounds(&self, x: i32, y: i32) -> bool {
-        if x < (self.len() as i32) && y < (self[0].len() as i32) && x >= 0 && y >= 0 {
+        if y < (self.len() as i32) && x < (self[0].len() as i32) && x >= 0 && y >= 0 {
             true
         } else {
             false
@@ -50,32 +90,51 @@ impl MapTiles<i32> for Map {
     }
 }
 
+fn empty_map(width: u32, height: u32) -> Map {
+    vec![vec![EMPTY_TILE; width as usize]; height as usize]
+}
+
 pub struct SandScene {
     map: Map,
 
-    last_spout: std::time::Instant,
+    // Simulated seconds since the spout last started.
+    spout_time: f32,
+    spout_period: f32,
+    spout_grains: u32,
+    color: [f32; 3],
+    palette: Option<Arc<Gradient>>,
 }
 
 impl SandScene {
-    pub fn new(width: usize, height: usize) -> Self {
-        let map: Map = vec![vec![EMPTY_TILE; width]; height];
-
+    pub fn new(width: u32, height: u32) -> Self {
         SandScene {
-            map,
-            last_spout: std::time::Instant::now(),
+            map: empty_map(width, height),
+            spout_time: 0.0,
+            spout_period: PARAMS[0].default.as_float(),
+            spout_grains: PARAMS[1].default.as_int() as u32,
+            color: PARAMS[2].default.as_color(),
+            palette: gradient::named(PARAMS[3].default.as_text()),
         }
     }
 
-    fn draw(&self, canvas: &mut Canvas) {
+    fn draw_map(&self, canvas: &mut Canvas) {
+        let max_y = self.map.len().saturating_sub(1).max(1) as f32;
         for y in 0..self.map.len() {
             for x in 0..self.map[y].len() {
                 let tile = self.map[y][x];
                 // let p = (tile.pressure / 100.0).powf(2.0).clamp(0.0, 1.0);
 
                 match tile.type_ {
-                    TileType::Sand => {
-                        canvas.set_pixel(x as u32, y as u32, 0.0, 0.9, 0.7);
-                    }
+                    TileType::Sand => match &self.palette {
+                        Some(palette) => {
+                            let [r, g, b] = palette.sample(y as f32 / max_y);
+                            canvas.set(x as u32, y as u32, [r, g, b, 1.0]);
+                        }
+                        None => {
+                            let [r, g, b] = self.color;
+                            canvas.set_pixel(x as u32, y as u32, r, g, b);
+                        }
+                    },
                     _ => {
                         canvas.set_pixel(x as u32, y as u32, 0.0, 0.0, 0.0);
                     }
@@ -100,16 +159,62 @@ impl SandScene {
 }
 
 impl Scene for SandScene {
-    fn tick(&mut self, canvas: &mut Canvas, _tick: &FrameTick) {
+    fn name(&self) -> &'static str {
+        "sand"
+    }
+
+    fn description(&self) -> &'static str {
+        "Falling sand that piles up and drains."
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.map = empty_map(width, height);
+    }
+
+    fn params(&self) -> &'static [ParamSpec] {
+        &PARAMS
+    }
+
+    fn get_param(&self, name: &str) -> Option<ParamValue> {
+        match name {
+            "spout_period" => Some(ParamValue::Float(self.spout_period)),
+            "spout_grains" => Some(ParamValue::Int(self.spout_grains as i64)),
+            "color" => Some(ParamValue::Color(self.color)),
+            "palette" => Some(param::palette_value(self.palette.as_ref())),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = param::validate(&PARAMS, name, value)?;
+        match name {
+            "spout_period" => self.spout_period = value.as_float(),
+            "spout_grains" => self.spout_grains = value.as_int() as u32,
+            "color" => self.color = value.as_color(),
+            "palette" => self.palette = gradient::named(value.as_text()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn update_rate(&self) -> UpdateRate {
+        UpdateRate::FixedStep(STEP_RATE)
+    }
+
+    fn step(&mut self, dt: f32) {
         let mut rng = rand::thread_rng();
 
-        if self.last_spout.elapsed().as_secs_f32() >= 1.0 {
-            if self.last_spout.elapsed().as_secs_f32() >= 2.0 {
-                self.last_spout = std::time::Instant::now();
+        self.spout_time += dt;
+        if self.spout_time >= self.spout_period / 2.0 {
+            if self.spout_time >= self.spout_period {
+                self.spout_time = 0.0;
             }
 
-            for _ in 0..5 {
-                let x: i32 = rng.gen_range(-80..80) + 128;
+            // Spouts across the middle 5/16ths either side of center.
+            let width = self.map[0].len() as i32;
+            let spread = (width * 5 / 16).max(1);
+            for _ in 0..self.spout_grains {
+                let x: i32 = (rng.gen_range(-spread..spread) + width / 2).clamp(0, width - 1);
                 self.map[0][x as usize] = Tile {
                     type_: TileType::Sand,
                     pressure: 0.0,
@@ -128,11 +233,7 @@ impl Scene for SandScene {
         });
 
         let mut to_update: Vec<(usize, usize)> = vec![];
-        let hehe2 = self.map[1].len();
-        let hehe = self.map[0].len();
-        eprint!("{hehe2}");
-        eprint!("{hehe}");
-        for y in 0..self.map[0].len() {
+        for y in 0..self.map.len() {
             for x in 0..self.map[y].len() {
                 to_update.push((x, y));
             }
@@ -408,18 +509,14 @@ impl Scene for SandScene {
         });
 
         if num_sand_tiles_in != num_sand_tiles_out {
-            println!(
-                "in: {}\tout: {}\t{}",
-                num_sand_tiles_in,
-                num_sand_tiles_out,
-                if num_sand_tiles_out > num_sand_tiles_in {
-                    ">"
-                } else {
-                    "<="
-                }
+            debug!(
+                "sand tiles changed in a step, in: {} out: {}",
+                num_sand_tiles_in, num_sand_tiles_out
             );
         }
+    }
 
-        self.draw(canvas);
+    fn draw(&mut self, canvas: &mut Canvas, _tick: &FrameTick, _alpha: f32) {
+        self.draw_map(canvas);
     }
 }
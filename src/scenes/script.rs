@@ -0,0 +1,366 @@
+use std::{
+    cell::{Cell, RefCell},
+    fs,
+    path::PathBuf,
+    rc::Rc,
+    time,
+};
+
+use embedded_graphics::{
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use log2::*;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use serde::Deserialize;
+use u8g2_fonts::{fonts, U8g2TextStyle};
+
+use crate::{canvas::linear_to_srgb, frame_tick::FrameTick, gradient, scenes, Canvas, Scene};
+
+// How often the script file is checked for changes.
+const RELOAD_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(1);
+// Operations between checks of the time budget.
+const BUDGET_CHECK_OPERATIONS: u64 = 256;
+const MAX_STRING_SIZE: usize = 4096;
+const MAX_ARRAY_SIZE: usize = 65536;
+const MAX_MAP_SIZE: usize = 4096;
+const MAX_CALL_LEVELS: usize = 32;
+// Rhai's release build limits, also used in debug builds, where the default
+// is low enough to reject ordinary expressions.
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_FUNCTION_EXPR_DEPTH: usize = 32;
+
+/// A scene written in Rhai, listed under `[scripts]` in the config.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptConfig {
+    pub path: String,
+    /// Milliseconds the script may run for per frame before it's stopped.
+    /// Whatever it drew by then is shown.
+    #[serde(default = "default_budget_ms")]
+    pub budget_ms: u64,
+}
+
+fn default_budget_ms() -> u64 {
+    10
+}
+
+/// A scene that runs a Rhai script's `tick(t, dt, width, height)` every frame,
+/// reloading the script when its file changes.
+///
+/// The script draws with `set_pixel(x, y, r, g, b)`, `fill(r, g, b)`,
+/// `rect(x, y, w, h, r, g, b)`, `line(x0, y0, x1, y1, r, g, b)`,
+/// `circle(x, y, radius, r, g, b)` and `text(x, y, string, r, g, b)`, with
+/// colors in sRGB `0.0..=1.0`, given either as three numbers or as an
+/// `[r, g, b]` array. `get_pixel(x, y)`, `hsv(h, s, v)` and `palette(name, t)`
+/// return such arrays, and `clear()` makes the canvas transparent. The canvas keeps what was
+/// drawn on the previous frame, and `this` is a map kept between frames for the
+/// script's own state. Top level statements run once, on each load.
+pub struct ScriptScene {
+    name: &'static str,
+    path: PathBuf,
+    budget: time::Duration,
+    engine: Engine,
+    target: Rc<RefCell<Canvas>>,
+    deadline: Rc<Cell<Option<time::Instant>>>,
+    script: Option<Script>,
+    modified: Option<time::SystemTime>,
+    last_check: Option<time::Instant>,
+    errors: scenes::ErrorLog,
+}
+
+struct Script {
+    ast: AST,
+    scope: Scope<'static>,
+    state: Dynamic,
+}
+
+impl ScriptScene {
+    /// `name` is the script's name in the config.
+    pub fn new(name: &str, config: &ScriptConfig, width: u32, height: u32) -> Self {
+        let target = Rc::new(RefCell::new(Canvas::new(width, height)));
+        let deadline: Rc<Cell<Option<time::Instant>>> = Rc::new(Cell::new(None));
+
+        let mut engine = Engine::new();
+        engine
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_ARRAY_SIZE)
+            .set_max_map_size(MAX_MAP_SIZE)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_FUNCTION_EXPR_DEPTH);
+        let name = scenes::intern(name);
+        engine.on_print(move |text| info!("{}: {}", name, text));
+        engine.on_debug(move |text, _, _| debug!("{}: {}", name, text));
+
+        let progress_deadline = deadline.clone();
+        engine.on_progress(move |operations| {
+            if operations % BUDGET_CHECK_OPERATIONS != 0 {
+                return None;
+            }
+            match progress_deadline.get() {
+                Some(deadline) if time::Instant::now() > deadline => Some(Dynamic::UNIT),
+                _ => None,
+            }
+        });
+        register_api(&mut engine, &target);
+
+        let mut scene = ScriptScene {
+            name,
+            path: PathBuf::from(&config.path),
+            budget: time::Duration::from_millis(config.budget_ms),
+            engine,
+            target,
+            deadline,
+            script: None,
+            modified: None,
+            last_check: None,
+            errors: scenes::ErrorLog::new("Script", name),
+        };
+        scene.reload_if_changed();
+        scene
+    }
+
+    fn reload_if_changed(&mut self) {
+        if self
+            .last_check
+            .is_some_and(|last| last.elapsed() < RELOAD_CHECK_INTERVAL)
+        {
+            return;
+        }
+        self.last_check = Some(time::Instant::now());
+
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        match self.load() {
+            Ok(script) => {
+                info!("Loaded script {} from {}", self.name, self.path.display());
+                self.script = Some(script);
+                self.errors.clear();
+            }
+            // Keep running the last version that loaded.
+            Err(e) => self
+                .errors
+                .report(format!("failed to load {}: {e}", self.path.display())),
+        }
+    }
+
+    fn load(&self) -> Result<Script, String> {
+        let source = fs::read_to_string(&self.path).map_err(|e| e.to_string())?;
+        let ast = self.engine.compile(source).map_err(|e| e.to_string())?;
+
+        let mut scope = Scope::new();
+        self.deadline.set(Some(time::Instant::now() + self.budget));
+        let result = self.engine.run_ast_with_scope(&mut scope, &ast);
+        self.deadline.set(None);
+        result.map_err(|e| describe(&e, self.budget))?;
+
+        Ok(Script {
+            ast,
+            scope,
+            state: Dynamic::from_map(Map::new()),
+        })
+    }
+}
+
+fn describe(error: &EvalAltResult, budget: time::Duration) -> String {
+    let stopped = matches!(error, EvalAltResult::ErrorTerminated(..));
+    scenes::describe(error, stopped, format_args!("{budget:?} budget"))
+}
+
+impl Scene for ScriptScene {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        "A Rhai script, reloaded when its file changes."
+    }
+
+    fn tick(&mut self, canvas: &mut Canvas, tick: &FrameTick) {
+        self.reload_if_changed();
+        let Some(script) = &mut self.script else {
+            return;
+        };
+
+        let (width, height) = (canvas.width as i64, canvas.height as i64);
+        std::mem::swap(canvas, &mut self.target.borrow_mut());
+        self.deadline.set(Some(time::Instant::now() + self.budget));
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut script.state),
+            &mut script.scope,
+            &script.ast,
+            "tick",
+            (tick.t as f64, tick.dt as f64, width, height),
+        );
+        self.deadline.set(None);
+        std::mem::swap(canvas, &mut self.target.borrow_mut());
+
+        if let Err(e) = result {
+            self.errors.report(describe(&e, self.budget));
+        }
+    }
+}
+
+/// A number from the script, which may be an integer or a float.
+fn num(v: &Dynamic) -> f32 {
+    v.as_float()
+        .map(|v| v as f32)
+        .or_else(|_| v.as_int().map(|v| v as f32))
+        .unwrap_or(0.0)
+}
+
+fn rgb(r: &Dynamic, g: &Dynamic, b: &Dynamic) -> [f32; 3] {
+    [num(r), num(g), num(b)].map(|c| c.clamp(0.0, 1.0))
+}
+
+fn rgb_array(color: &Array) -> [f32; 3] {
+    match color.as_slice() {
+        [r, g, b] => rgb(r, g, b),
+        _ => [0.0; 3],
+    }
+}
+
+fn rgb888(rgb: [f32; 3]) -> Rgb888 {
+    let [r, g, b] = rgb.map(|c| (c * 255.0).round() as u8);
+    Rgb888::new(r, g, b)
+}
+
+fn to_array(rgb: [f32; 3]) -> Array {
+    rgb.iter().map(|&c| Dynamic::from_float(c as f64)).collect()
+}
+
+fn point(x: f32, y: f32) -> Point {
+    Point::new(x.round() as i32, y.round() as i32)
+}
+
+/// Registers `$name` twice, taking numbers for `$arg`s followed by either
+/// `r, g, b` or an `[r, g, b]` array, and calls `$draw` with the numbers and
+/// the color.
+macro_rules! register_drawing {
+    ($engine:expr, $name:literal, |$($arg:ident),*| $draw:expr) => {{
+        let draw = Rc::new($draw);
+        let draw_rgb = draw.clone();
+        $engine.register_fn(
+            $name,
+            move |$($arg: Dynamic,)* r: Dynamic, g: Dynamic, b: Dynamic| {
+                draw_rgb($(num(&$arg),)* rgb(&r, &g, &b))
+            },
+        );
+        $engine.register_fn($name, move |$($arg: Dynamic,)* color: Array| {
+            draw($(num(&$arg),)* rgb_array(&color))
+        });
+    }};
+}
+
+fn register_api(engine: &mut Engine, target: &Rc<RefCell<Canvas>>) {
+    let canvas = target.clone();
+    register_drawing!(engine, "set_pixel", |x, y| {
+        move |x: f32, y: f32, [r, g, b]: [f32; 3]| {
+            if x >= 0.0 && y >= 0.0 {
+                canvas.borrow_mut().set_pixel(x as u32, y as u32, r, g, b);
+            }
+        }
+    });
+    let canvas = target.clone();
+    engine.register_fn("fill", move |r: Dynamic, g: Dynamic, b: Dynamic| {
+        let [r, g, b] = rgb(&r, &g, &b);
+        canvas.borrow_mut().clear_with_color(r, g, b)
+    });
+    let canvas = target.clone();
+    engine.register_fn("fill", move |color: Array| {
+        let [r, g, b] = rgb_array(&color);
+        canvas.borrow_mut().clear_with_color(r, g, b)
+    });
+    let canvas = target.clone();
+    register_drawing!(engine, "rect", |x, y, w, h| {
+        move |x: f32, y: f32, w: f32, h: f32, color: [f32; 3]| {
+            let size = Size::new(w.max(0.0).round() as u32, h.max(0.0).round() as u32);
+            Rectangle::new(point(x, y), size)
+                .into_styled(PrimitiveStyle::with_fill(rgb888(color)))
+                .draw(&mut *canvas.borrow_mut())
+                .ok();
+        }
+    });
+    let canvas = target.clone();
+    register_drawing!(engine, "line", |x0, y0, x1, y1| {
+        move |x0: f32, y0: f32, x1: f32, y1: f32, color: [f32; 3]| {
+            Line::new(point(x0, y0), point(x1, y1))
+                .into_styled(PrimitiveStyle::with_stroke(rgb888(color), 1))
+                .draw(&mut *canvas.borrow_mut())
+                .ok();
+        }
+    });
+    let canvas = target.clone();
+    register_drawing!(engine, "circle", |x, y, radius| {
+        move |x: f32, y: f32, radius: f32, color: [f32; 3]| {
+            let diameter = (radius.max(0.0) * 2.0).round() as u32 + 1;
+            Circle::with_center(point(x, y), diameter)
+                .into_styled(PrimitiveStyle::with_fill(rgb888(color)))
+                .draw(&mut *canvas.borrow_mut())
+                .ok();
+        }
+    });
+
+    let draw_text = {
+        let canvas = target.clone();
+        Rc::new(move |x: f32, y: f32, text: &str, color: [f32; 3]| {
+            let style = U8g2TextStyle::new(fonts::u8g2_font_5x7_tf, rgb888(color));
+            Text::with_baseline(text, point(x, y), style, Baseline::Top)
+                .draw(&mut *canvas.borrow_mut())
+                .ok();
+        })
+    };
+    let draw = draw_text.clone();
+    engine.register_fn(
+        "text",
+        move |x: Dynamic, y: Dynamic, text: &str, r: Dynamic, g: Dynamic, b: Dynamic| {
+            draw(num(&x), num(&y), text, rgb(&r, &g, &b))
+        },
+    );
+    engine.register_fn(
+        "text",
+        move |x: Dynamic, y: Dynamic, text: &str, color: Array| {
+            draw_text(num(&x), num(&y), text, rgb_array(&color))
+        },
+    );
+
+    let canvas = target.clone();
+    engine.register_fn("get_pixel", move |x: Dynamic, y: Dynamic| -> Array {
+        let (x, y) = (num(&x), num(&y));
+        if x < 0.0 || y < 0.0 {
+            return to_array([0.0; 3]);
+        }
+        to_array(canvas.borrow().get_pixel(x as u32, y as u32))
+    });
+    let canvas = target.clone();
+    engine.register_fn("clear", move || canvas.borrow_mut().clear());
+
+    engine.register_fn("hsv", |h: Dynamic, s: Dynamic, v: Dynamic| -> Array {
+        let (h, s, v) = (num(&h).rem_euclid(360.0), num(&s), num(&v));
+        let c = v * s;
+        let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+        let [r, g, b] = match (h / 60.0) as u32 {
+            0 => [c, x, 0.0],
+            1 => [x, c, 0.0],
+            2 => [0.0, c, x],
+            3 => [0.0, x, c],
+            4 => [x, 0.0, c],
+            _ => [c, 0.0, x],
+        };
+        to_array([r, g, b].map(|channel| channel + v - c))
+    });
+    engine.register_fn("palette", |name: &str, t: Dynamic| -> Array {
+        match gradient::named(name) {
+            Some(palette) => to_array(palette.sample(num(&t)).map(linear_to_srgb)),
+            None => to_array([0.0; 3]),
+        }
+    });
+}
@@ -0,0 +1,791 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use crate::{
+    color::{decode_srgb, encode_srgb},
+    frame_tick::FrameTick,
+    gradient::{self, Gradient},
+    noise,
+    scenes::{
+        self,
+        param::{self, ParamKind, ParamSpec, ParamValue},
+    },
+    Canvas, Scene,
+};
+
+const PARAMS: [ParamSpec; 1] = [ParamSpec {
+    name: "speed",
+    description: "How fast t advances.",
+    kind: ParamKind::Float {
+        min: 0.0,
+        max: 10.0,
+    },
+    default: ParamValue::Float(1.0),
+}];
+
+// Variables every program can read, in the order of their slots.
+const INPUTS: [&str; 7] = ["x", "y", "px", "py", "width", "height", "t"];
+const CONSTANTS: [&str; 2] = ["pi", "tau"];
+
+// Limits that keep a runaway shader from overflowing the stack while it's
+// parsed: how deeply parentheses, calls and unary operators can nest, and how
+// long a shader can be, which bounds chains like `a + a + a + ...`.
+const MAX_DEPTH: usize = 64;
+const MAX_TOKENS: usize = 4096;
+
+type Value = [f32; 3];
+
+/// One instruction of a compiled program, run on a stack of values.
+enum Op {
+    Const(Value),
+    Load(usize),
+    Store(usize),
+    Unary(fn(f32) -> f32),
+    Binary(fn(f32, f32) -> f32),
+    Ternary(fn(f32, f32, f32) -> f32),
+    /// Pops this many values and pushes the length of the vector they form.
+    Length(usize),
+    Noise2,
+    Noise3,
+    Palette(Arc<Gradient>),
+    Rgb,
+    Hsv,
+}
+
+/// A shader compiled to a flat list of stack operations, evaluated once per
+/// pixel.
+///
+/// Every value is three numbers, red, green and blue. Numbers are all three
+/// set to the same value, so arithmetic and functions work the same on both,
+/// one channel at a time. Where a single number is needed, like `palette`'s
+/// position, a color's red channel is used.
+pub struct Program {
+    ops: Vec<Op>,
+    slots: usize,
+}
+
+impl Program {
+    /// Compiles a program: optional `name = expression;` assignments, then the
+    /// expression for the color, in sRGB `0.0..=1.0`. A number is drawn as
+    /// gray.
+    pub fn compile(source: &str) -> Result<Program, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let mut compiler = Compiler {
+            names: INPUTS.iter().map(|name| name.to_string()).collect(),
+            ops: vec![],
+        };
+
+        loop {
+            // An assignment is a name followed by `=`.
+            if let (Some(Token::Ident(name)), Some(Token::Symbol('='))) =
+                (parser.peek(0), parser.peek(1))
+            {
+                let name = name.clone();
+                parser.pos += 2;
+                let expr = parser.expr()?;
+                parser.expect(';')?;
+                compiler.assign(name, &expr)?;
+                continue;
+            }
+
+            let expr = parser.expr()?;
+            if parser.peek(0) == Some(&Token::Symbol(';')) {
+                parser.pos += 1;
+            }
+            if parser.pos < parser.tokens.len() {
+                return Err(format!("unexpected {}", parser.describe()));
+            }
+            compiler.compile(&expr)?;
+            break;
+        }
+
+        Ok(Program {
+            slots: compiler.names.len(),
+            ops: compiler.ops,
+        })
+    }
+
+    /// Runs the program. `slots` holds the inputs, in the order of `INPUTS`,
+    /// followed by room for every assigned name.
+    fn eval(&self, stack: &mut Vec<Value>, slots: &mut [Value]) -> Value {
+        stack.clear();
+        for op in &self.ops {
+            match op {
+                Op::Const(v) => stack.push(*v),
+                Op::Load(slot) => stack.push(slots[*slot]),
+                Op::Store(slot) => slots[*slot] = stack.pop().unwrap(),
+                Op::Unary(f) => {
+                    let a = stack.last_mut().unwrap();
+                    *a = a.map(f);
+                }
+                Op::Binary(f) => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.last_mut().unwrap();
+                    *a = [f(a[0], b[0]), f(a[1], b[1]), f(a[2], b[2])];
+                }
+                Op::Ternary(f) => {
+                    let c = stack.pop().unwrap();
+                    let b = stack.pop().unwrap();
+                    let a = stack.last_mut().unwrap();
+                    *a = [
+                        f(a[0], b[0], c[0]),
+                        f(a[1], b[1], c[1]),
+                        f(a[2], b[2], c[2]),
+                    ];
+                }
+                Op::Length(count) => {
+                    let start = stack.len() - count;
+                    let mut sum = [0.0; 3];
+                    for v in stack.drain(start..) {
+                        for c in 0..3 {
+                            sum[c] += v[c] * v[c];
+                        }
+                    }
+                    stack.push(sum.map(f32::sqrt));
+                }
+                Op::Noise2 => {
+                    let y = stack.pop().unwrap()[0];
+                    let x = stack.pop().unwrap()[0];
+                    stack.push([noise::simplex2(x, y); 3]);
+                }
+                Op::Noise3 => {
+                    let z = stack.pop().unwrap()[0];
+                    let y = stack.pop().unwrap()[0];
+                    let x = stack.pop().unwrap()[0];
+                    stack.push([noise::simplex3(x, y, z); 3]);
+                }
+                Op::Palette(palette) => {
+                    let t = stack.pop().unwrap()[0];
+                    stack.push(palette.sample(t).map(encode_srgb));
+                }
+                Op::Rgb => {
+                    let b = stack.pop().unwrap()[0];
+                    let g = stack.pop().unwrap()[0];
+                    let r = stack.pop().unwrap()[0];
+                    stack.push([r, g, b]);
+                }
+                Op::Hsv => {
+                    let v = stack.pop().unwrap()[0];
+                    let s = stack.pop().unwrap()[0];
+                    let h = stack.pop().unwrap()[0];
+                    stack.push(hsv(h, s, v));
+                }
+            }
+        }
+        stack.pop().unwrap()
+    }
+}
+
+/// `h` in turns, so `fract` wraps it.
+fn hsv(h: f32, s: f32, v: f32) -> Value {
+    [5.0, 3.0, 1.0].map(|n| {
+        let k = (n + h.rem_euclid(1.0) * 6.0) % 6.0;
+        v - v * s.clamp(0.0, 1.0) * k.min(4.0 - k).clamp(0.0, 1.0)
+    })
+}
+
+fn fract(v: f32) -> f32 {
+    v - v.floor()
+}
+
+/// The remainder with the sign of `b`, as in GLSL.
+fn modulo(a: f32, b: f32) -> f32 {
+    a - b * (a / b).floor()
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn unary_fn(name: &str) -> Option<fn(f32) -> f32> {
+    Some(match name {
+        "sin" => f32::sin,
+        "cos" => f32::cos,
+        "tan" => f32::tan,
+        "asin" => f32::asin,
+        "acos" => f32::acos,
+        "atan" => f32::atan,
+        "abs" => f32::abs,
+        "floor" => f32::floor,
+        "ceil" => f32::ceil,
+        "round" => f32::round,
+        "fract" => fract,
+        "sqrt" => f32::sqrt,
+        "exp" => f32::exp,
+        "log" => f32::ln,
+        "sign" => |v| if v == 0.0 { 0.0 } else { v.signum() },
+        _ => return None,
+    })
+}
+
+fn binary_fn(name: &str) -> Option<fn(f32, f32) -> f32> {
+    Some(match name {
+        "min" => f32::min,
+        "max" => f32::max,
+        "pow" => f32::powf,
+        "atan2" => f32::atan2,
+        "mod" => modulo,
+        "step" => |edge, x| if x < edge { 0.0 } else { 1.0 },
+        _ => return None,
+    })
+}
+
+fn ternary_fn(name: &str) -> Option<fn(f32, f32, f32) -> f32> {
+    Some(match name {
+        "mix" => |a, b, t| a + (b - a) * t,
+        "clamp" => |x, min, max| x.max(min).min(max),
+        "smoothstep" => smoothstep,
+        _ => return None,
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Str(String),
+    Symbol(char),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let text = &source[start..end];
+            let number = text
+                .parse()
+                .map_err(|_| format!("invalid number {text:?} at {start}"))?;
+            tokens.push(Token::Number(number));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Ident(source[start..end].to_string()));
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, end)) if end == c => break,
+                    Some((_, other)) => text.push(other),
+                    None => return Err(format!("unterminated string at {start}")),
+                }
+            }
+            tokens.push(Token::Str(text));
+        } else if "+-*/%^(),=;".contains(c) {
+            chars.next();
+            tokens.push(Token::Symbol(c));
+        } else {
+            return Err(format!("unexpected {c:?} at {start}"));
+        }
+        if tokens.len() > MAX_TOKENS {
+            return Err(format!("longer than {MAX_TOKENS} tokens"));
+        }
+    }
+    Ok(tokens)
+}
+
+enum Expr {
+    Number(f32),
+    Name(String),
+    Str(String),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+/// A recursive descent parser, from lowest precedence to highest: `+ -`,
+/// `* / %`, unary `-`, then `^`, which is right associative.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // How many `unary` calls are under way, which every level of nesting
+    // goes through.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self, ahead: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + ahead)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn describe(&self) -> String {
+        match self.peek(0) {
+            Some(Token::Number(n)) => format!("number {n}"),
+            Some(Token::Ident(name)) => format!("name {name}"),
+            Some(Token::Str(text)) => format!("string {text:?}"),
+            Some(Token::Symbol(c)) => format!("{c:?}"),
+            None => "end of shader".to_string(),
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        if self.peek(0) == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected {symbol:?}, found {}", self.describe()))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut left = self.term()?;
+        while let Some(Token::Symbol(op @ ('+' | '-'))) = self.peek(0) {
+            let op = *op;
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.term()?));
+        }
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Symbol(op @ ('*' | '/' | '%'))) = self.peek(0) {
+            let op = *op;
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("nested deeper than {MAX_DEPTH} levels"));
+        }
+        self.depth += 1;
+        let expr = self.prefixed();
+        self.depth -= 1;
+        expr
+    }
+
+    fn prefixed(&mut self) -> Result<Expr, String> {
+        if self.peek(0) == Some(&Token::Symbol('-')) {
+            self.pos += 1;
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        let base = self.atom()?;
+        if self.peek(0) == Some(&Token::Symbol('^')) {
+            self.pos += 1;
+            return Ok(Expr::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let found = self.describe();
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Str(text)) => Ok(Expr::Str(text)),
+            Some(Token::Symbol('(')) => {
+                let expr = self.expr()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek(0) != Some(&Token::Symbol('(')) {
+                    return Ok(Expr::Name(name));
+                }
+                self.pos += 1;
+                let mut args = vec![];
+                if self.peek(0) != Some(&Token::Symbol(')')) {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.peek(0) != Some(&Token::Symbol(',')) {
+                            break;
+                        }
+                        self.pos += 1;
+                    }
+                }
+                self.expect(')')?;
+                Ok(Expr::Call(name, args))
+            }
+            _ => Err(format!("expected a value, found {found}")),
+        }
+    }
+}
+
+struct Compiler {
+    // Names of the slots, inputs first.
+    names: Vec<String>,
+    ops: Vec<Op>,
+}
+
+impl Compiler {
+    fn assign(&mut self, name: String, expr: &Expr) -> Result<(), String> {
+        // Inputs are only set once per frame or pixel, so an assignment would
+        // carry over to the next pixel.
+        if INPUTS.contains(&name.as_str()) || CONSTANTS.contains(&name.as_str()) {
+            return Err(format!("can't assign to {name}"));
+        }
+        self.compile(expr)?;
+        let slot = match self.names.iter().position(|n| *n == name) {
+            Some(slot) => slot,
+            None => {
+                self.names.push(name);
+                self.names.len() - 1
+            }
+        };
+        self.ops.push(Op::Store(slot));
+        Ok(())
+    }
+
+    fn compile(&mut self, expr: &Expr) -> Result<(), String> {
+        match expr {
+            Expr::Number(n) => self.ops.push(Op::Const([*n; 3])),
+            Expr::Name(name) => match name.as_str() {
+                "pi" => self.ops.push(Op::Const([PI; 3])),
+                "tau" => self.ops.push(Op::Const([2.0 * PI; 3])),
+                _ => {
+                    let slot = self
+                        .names
+                        .iter()
+                        .position(|n| n == name)
+                        .ok_or_else(|| format!("unknown name {name}"))?;
+                    self.ops.push(Op::Load(slot));
+                }
+            },
+            Expr::Str(text) => {
+                return Err(format!(
+                    "unexpected string {text:?}, only palette names are strings"
+                ))
+            }
+            Expr::Neg(a) => {
+                self.compile(a)?;
+                self.ops.push(Op::Unary(|v| -v));
+            }
+            Expr::Binary(op, a, b) => {
+                self.compile(a)?;
+                self.compile(b)?;
+                self.ops.push(Op::Binary(match op {
+                    '+' => |a, b| a + b,
+                    '-' => |a, b| a - b,
+                    '*' => |a, b| a * b,
+                    '/' => |a, b| a / b,
+                    '%' => modulo,
+                    _ => f32::powf,
+                }));
+            }
+            Expr::Call(name, args) => self.call(name, args)?,
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr]) -> Result<(), String> {
+        let arity = |expected: &str| format!("{name} takes {expected}, not {}", args.len());
+
+        if name == "palette" {
+            let (palette, t) = match args {
+                [Expr::Str(palette), t] => (palette, t),
+                _ => return Err("palette takes a palette name and a position".to_string()),
+            };
+            let gradient =
+                gradient::named(palette).ok_or_else(|| format!("unknown palette {palette}"))?;
+            self.compile(t)?;
+            self.ops.push(Op::Palette(gradient));
+            return Ok(());
+        }
+
+        for arg in args {
+            self.compile(arg)?;
+        }
+        let op = match (name, args.len()) {
+            ("length", 1..=3) => Op::Length(args.len()),
+            ("length", _) => return Err(arity("1 to 3 arguments")),
+            ("noise", 2) => Op::Noise2,
+            ("noise", 3) => Op::Noise3,
+            ("noise", _) => return Err(arity("2 or 3 arguments")),
+            ("rgb", 3) => Op::Rgb,
+            ("hsv", 3) => Op::Hsv,
+            ("rgb" | "hsv", _) => return Err(arity("3 arguments")),
+            _ => match (unary_fn(name), binary_fn(name), ternary_fn(name)) {
+                (Some(f), _, _) if args.len() == 1 => Op::Unary(f),
+                (_, Some(f), _) if args.len() == 2 => Op::Binary(f),
+                (_, _, Some(f)) if args.len() == 3 => Op::Ternary(f),
+                (Some(_), _, _) => return Err(arity("1 argument")),
+                (_, Some(_), _) => return Err(arity("2 arguments")),
+                (_, _, Some(_)) => return Err(arity("3 arguments")),
+                _ => return Err(format!("unknown function {name}")),
+            },
+        };
+        self.ops.push(op);
+        Ok(())
+    }
+}
+
+/// A scene drawn by a shader program from the config, evaluated for every
+/// pixel. See `Program::compile` for the language.
+///
+/// The program reads `x` and `y`, each `0.0..1.0` across the canvas, `px` and
+/// `py` in pixels, `width`, `height`, `t` in seconds, `pi` and `tau`.
+pub struct ShaderScene {
+    name: &'static str,
+    program: Program,
+    speed: f32,
+    stack: Vec<Value>,
+    slots: Vec<Value>,
+}
+
+impl ShaderScene {
+    /// `name` is the shader's name in the config.
+    pub fn new(name: &str, source: &str, _width: u32, _height: u32) -> Result<Self, String> {
+        let program = Program::compile(source).map_err(|e| format!("shader {name}: {e}"))?;
+        Ok(ShaderScene {
+            name: scenes::intern(name),
+            slots: vec![[0.0; 3]; program.slots],
+            program,
+            speed: PARAMS[0].default.as_float(),
+            stack: vec![],
+        })
+    }
+}
+
+impl Scene for ShaderScene {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        "A shader expression from the config, evaluated for every pixel."
+    }
+
+    fn params(&self) -> &'static [ParamSpec] {
+        &PARAMS
+    }
+
+    fn get_param(&self, name: &str) -> Option<ParamValue> {
+        match name {
+            "speed" => Some(ParamValue::Float(self.speed)),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = param::validate(&PARAMS, name, value)?;
+        if name == "speed" {
+            self.speed = value.as_float();
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, canvas: &mut Canvas, tick: &FrameTick) {
+        let (width, height) = (canvas.width as f32, canvas.height as f32);
+        self.slots[4] = [width; 3];
+        self.slots[5] = [height; 3];
+        self.slots[6] = [tick.t * self.speed; 3];
+
+        for (px, py, pixel) in canvas.enumerate_pixels_mut() {
+            let (px, py) = (px as f32, py as f32);
+            self.slots[0] = [(px + 0.5) / width; 3];
+            self.slots[1] = [(py + 0.5) / height; 3];
+            self.slots[2] = [px; 3];
+            self.slots[3] = [py; 3];
+
+            let [r, g, b] = self
+                .program
+                .eval(&mut self.stack, &mut self.slots)
+                .map(|c| if c.is_nan() { 0.0 } else { decode_srgb(c) });
+            *pixel = [r, g, b, 1.0];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `source` once with every input zero.
+    fn eval(source: &str) -> Value {
+        let program = Program::compile(source).unwrap();
+        let mut slots = vec![[0.0; 3]; program.slots];
+        program.eval(&mut vec![], &mut slots)
+    }
+
+    fn assert_close(a: Value, b: Value) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4),
+            "{a:?} != {b:?}"
+        );
+    }
+
+    fn render(source: &str, t: f32) -> Canvas {
+        let mut scene = ShaderScene::new("test", source, 8, 4).unwrap();
+        let mut canvas = Canvas::new(8, 4);
+        scene.tick(&mut canvas, &FrameTick::at(t));
+        canvas
+    }
+
+    #[test]
+    fn inputs_and_constants_cant_be_assigned() {
+        for name in INPUTS.iter().chain(&CONSTANTS) {
+            let source = format!("{name} = {name} * 2; 0");
+            assert!(Program::compile(&source).is_err(), "{source}");
+        }
+        assert!(Program::compile("t = t * 2; t").is_err());
+    }
+
+    #[test]
+    fn assignments_dont_carry_between_pixels() {
+        let canvas = render("a = t * 0.25; a = a * 2; a", 1.0);
+        let first = canvas.pixels[0];
+        assert!((canvas.get_pixel(0, 0)[0] - 0.5).abs() < 1e-4);
+        assert!(canvas.pixels.iter().all(|pixel| *pixel == first));
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let parens = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        assert!(Program::compile(&parens).is_err());
+        assert!(Program::compile(&"-".repeat(10_000)).is_err());
+        assert!(Program::compile(&format!("1{}", " + 1".repeat(10_000))).is_err());
+        let nested = format!(
+            "{}1{}",
+            "(".repeat(MAX_DEPTH - 1),
+            ")".repeat(MAX_DEPTH - 1)
+        );
+        assert!(Program::compile(&nested).is_ok());
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            tokenize("a_1 = .5 * 'fire';\n").unwrap(),
+            [
+                Token::Ident("a_1".to_string()),
+                Token::Symbol('='),
+                Token::Number(0.5),
+                Token::Symbol('*'),
+                Token::Str("fire".to_string()),
+                Token::Symbol(';'),
+            ]
+        );
+        assert_eq!(
+            tokenize("\"it's\"").unwrap(),
+            [Token::Str("it's".to_string())]
+        );
+    }
+
+    #[test]
+    fn malformed_tokens_are_errors() {
+        assert!(tokenize("1.2.3").is_err());
+        assert!(tokenize("palette('fire, x)").is_err());
+        assert!(tokenize("x $ y").is_err());
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_close(eval("1 + 2 * 3"), [7.0; 3]);
+        assert_close(eval("(1 + 2) * 3"), [9.0; 3]);
+        assert_close(eval("8 - 4 - 2"), [2.0; 3]);
+        assert_close(eval("8 / 4 / 2"), [1.0; 3]);
+        assert_close(eval("2 ^ 3 ^ 2"), [512.0; 3]);
+        assert_close(eval("-2 ^ 2"), [-4.0; 3]);
+        assert_close(eval("2 * -3"), [-6.0; 3]);
+        assert_close(eval("-7 % 3"), [2.0; 3]);
+    }
+
+    #[test]
+    fn functions() {
+        assert_close(eval("sin(pi / 2) + cos(0)"), [2.0; 3]);
+        assert_close(eval("fract(-0.25)"), [0.75; 3]);
+        assert_close(eval("sign(0) + sign(-3)"), [-1.0; 3]);
+        assert_close(eval("min(1, 2) + max(1, 2)"), [3.0; 3]);
+        assert_close(eval("mod(-1, 4)"), [3.0; 3]);
+        assert_close(eval("step(0.5, 0.4) + step(0.5, 0.5)"), [1.0; 3]);
+        assert_close(eval("mix(2, 4, 0.25)"), [2.5; 3]);
+        assert_close(eval("clamp(5, 0, 1)"), [1.0; 3]);
+        assert_close(eval("smoothstep(0, 1, 0.5)"), [0.5; 3]);
+        assert_close(eval("length(3, 4)"), [5.0; 3]);
+        assert_close(eval("noise(1.5, 2.5)"), [noise::simplex2(1.5, 2.5); 3]);
+        assert_close(eval("tau - 2 * pi"), [0.0; 3]);
+    }
+
+    #[test]
+    fn colors() {
+        assert_close(eval("rgb(0.1, 0.2, 0.3)"), [0.1, 0.2, 0.3]);
+        assert_close(eval("rgb(0.1, 0.2, 0.3) * 2"), [0.2, 0.4, 0.6]);
+        assert_close(eval("max(rgb(1, 0, 0.5), 0.25)"), [1.0, 0.25, 0.5]);
+        assert_close(eval("hsv(0, 1, 1)"), [1.0, 0.0, 0.0]);
+        assert_close(eval("hsv(1 / 3, 1, 1)"), [0.0, 1.0, 0.0]);
+        assert_close(eval("hsv(5 / 3, 1, 0.5)"), [0.0, 0.0, 0.5]);
+        assert_close(eval("hsv(0.5, 0, 0.5)"), [0.5; 3]);
+
+        let palette = &gradient::names()[0];
+        let expected = gradient::named(palette)
+            .unwrap()
+            .sample(0.5)
+            .map(encode_srgb);
+        assert_close(eval(&format!("palette('{palette}', 0.5)")), expected);
+    }
+
+    #[test]
+    fn inputs() {
+        let canvas = render("rgb(x, y, t / 10)", 2.0);
+        assert_close(canvas.get_pixel(0, 0), [1.0 / 16.0, 1.0 / 8.0, 0.2]);
+        assert_close(canvas.get_pixel(7, 3), [15.0 / 16.0, 7.0 / 8.0, 0.2]);
+        let canvas = render("rgb(px / width, py / height, 0)", 0.0);
+        assert_close(canvas.get_pixel(4, 2), [0.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn malformed_programs_are_errors() {
+        for source in [
+            "",
+            "1 +",
+            "(1",
+            "1)",
+            "1 2",
+            "a = 1",
+            "a = 1; ",
+            "sin(1,)",
+            "unknown",
+            "a = b; a",
+            "unknown(1)",
+            "sin(1, 2)",
+            "mix(1, 2)",
+            "length()",
+            "noise(1)",
+            "rgb(1, 2)",
+            "'fire'",
+            "palette(0.5)",
+            "palette('no such palette', 0.5)",
+        ] {
+            assert!(Program::compile(source).is_err(), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn nan_is_black() {
+        let canvas = render("sqrt(-1)", 0.0);
+        assert!(canvas
+            .pixels
+            .iter()
+            .all(|pixel| *pixel == [0.0, 0.0, 0.0, 1.0]));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{frame_tick::FrameTick, Canvas, Scene, UpdateRate};
+
+// White, yellow, cyan, green, magenta, red, blue, black.
+const COLOR_BARS: [[f32; 3]; 8] = [
+    [1.0, 1.0, 1.0],
+    [1.0, 1.0, 0.0],
+    [0.0, 1.0, 1.0],
+    [0.0, 1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [0.0, 0.0, 0.0],
+];
+const GRAY_STEPS: u32 = 16;
+
+/// A static pattern for tuning output calibration. From the top: smooth
+/// white, red, green and blue ramps, a gray step wedge, and color bars.
+///
+/// The ramps should look even with no visible jump near black, the wedge
+/// steps should all be distinct and neutral, and the bars should match
+/// across panels.
+pub struct TestPatternScene {}
+
+impl TestPatternScene {
+    pub fn new(_width: u32, _height: u32) -> Self {
+        TestPatternScene {}
+    }
+}
+
+impl Scene for TestPatternScene {
+    fn name(&self) -> &'static str {
+        "test_pattern"
+    }
+
+    fn description(&self) -> &'static str {
+        "Ramps, a step wedge and color bars for tuning calibration."
+    }
+
+    fn update_rate(&self) -> UpdateRate {
+        UpdateRate::Limited(1.0)
+    }
+
+    fn tick(&mut self, canvas: &mut Canvas, _tick: &FrameTick) {
+        let ramps: [[f32; 3]; 4] = [
+            [1.0, 1.0, 1.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ];
+        let bands = ramps.len() as u32 + 2;
+        let max_x = canvas.width.saturating_sub(1).max(1) as f32;
+
+        for y in 0..canvas.height {
+            let band = y * bands / canvas.height;
+            for x in 0..canvas.width {
+                let [r, g, b] = match band as usize {
+                    ramp if ramp < ramps.len() => {
+                        let level = x as f32 / max_x;
+                        ramps[ramp].map(|c| c * level)
+                    }
+                    ramp if ramp == ramps.len() => {
+                        let step = x * GRAY_STEPS / canvas.width;
+                        [step as f32 / (GRAY_STEPS - 1) as f32; 3]
+                    }
+                    _ => COLOR_BARS[(x * COLOR_BARS.len() as u32 / canvas.width) as usize],
+                };
+                canvas.set_pixel(x, y, r, g, b);
+            }
+        }
+    }
+}
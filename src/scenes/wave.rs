@@ -1,41 +1,116 @@
-use palette::{FromColor, Oklch, Srgb};
-use rand::Rng;
+use std::{borrow::Cow, f32::consts::TAU, sync::Arc};
 
-use crate::{Canvas, frame_tick::FrameTick, Scene};
+use rand::Rng;
 
-const SEARCH_RADIUS: i32 = 2;
-const KERNEL_SIZE: usize = (SEARCH_RADIUS * 2 + 1) as usize;
+use crate::{
+    color::oklab_to_linear,
+    frame_tick::FrameTick,
+    gradient::{self, Gradient},
+    scenes::param::{self, ParamKind, ParamSpec, ParamValue},
+    Canvas, Scene, UpdateRate,
+};
+
+const STEP_RATE: f32 = 30.0;
+
+const PARAMS: [ParamSpec; 7] = [
+    ParamSpec {
+        name: "speed",
+        description: "How fast waves fade and grow, and colors cycle.",
+        kind: ParamKind::Float {
+            min: 0.0,
+            max: 10.0,
+        },
+        default: ParamValue::Float(1.0),
+    },
+    ParamSpec {
+        name: "search_radius",
+        description: "How far away neighbors feed a growing pixel.",
+        kind: ParamKind::Int { min: 1, max: 4 },
+        default: ParamValue::Int(2),
+    },
+    ParamSpec {
+        name: "decay_min",
+        description: "Lower bound of the random fade per second.",
+        kind: ParamKind::Float { min: 0.0, max: 1.0 },
+        default: ParamValue::Float(0.2),
+    },
+    ParamSpec {
+        name: "decay_max",
+        description: "Upper bound of the random fade per second.",
+        kind: ParamKind::Float { min: 0.0, max: 1.0 },
+        default: ParamValue::Float(0.4),
+    },
+    ParamSpec {
+        name: "grow_min",
+        description: "Lower bound of the random level a neighbor needs to feed growth.",
+        kind: ParamKind::Float { min: 0.0, max: 1.0 },
+        default: ParamValue::Float(0.4),
+    },
+    ParamSpec {
+        name: "grow_max",
+        description: "Upper bound of the random level a neighbor needs to feed growth.",
+        kind: ParamKind::Float { min: 0.0, max: 1.0 },
+        default: ParamValue::Float(0.6),
+    },
+    ParamSpec {
+        name: "palette",
+        description: "Hues the waves cycle through. The wave height sets the lightness.",
+        kind: ParamKind::Palette,
+        default: ParamValue::Text(Cow::Borrowed("")),
+    },
+];
+
+/// Weights of the neighbors within `radius` for `grow_step`, falling off with
+/// distance.
+pub struct Kernel {
+    radius: i32,
+    weights: Vec<f32>,
+}
 
-type Kernel = [[f32; KERNEL_SIZE]; KERNEL_SIZE];
+impl Kernel {
+    fn weight(&self, u: i32, v: i32) -> f32 {
+        let size = self.radius * 2 + 1;
+        self.weights[((v + self.radius) * size + u + self.radius) as usize]
+    }
+}
 
 pub struct WaveScene {
+    width: u32,
+    height: u32,
     map: Vec<f32>,
     last_map: Vec<f32>,
     weights: Kernel,
     speed: f32,
+    decay: (f32, f32),
+    grow_threshold: (f32, f32),
+    palette: Option<Arc<Gradient>>,
 }
 
 impl WaveScene {
-    pub fn new(canvas: &Canvas, speed: f32) -> Self {
-        let mut rng = rand::thread_rng();
-
-        let mut map = vec![0.0_f32; (canvas.width * canvas.height) as usize];
-        for i in &mut map {
-            *i = rng.gen();
-        }
-
-        let weights = gen_weights();
+    pub fn new(width: u32, height: u32) -> Self {
+        let map = random_map(width, height);
 
         WaveScene {
+            width,
+            height,
             last_map: map.clone(),
             map,
-            weights,
-            speed,
+            weights: gen_weights(PARAMS[1].default.as_int() as i32),
+            speed: PARAMS[0].default.as_float(),
+            decay: (PARAMS[2].default.as_float(), PARAMS[3].default.as_float()),
+            grow_threshold: (PARAMS[4].default.as_float(), PARAMS[5].default.as_float()),
+            palette: gradient::named(PARAMS[6].default.as_text()),
         }
     }
 
-    fn draw_map(&self, canvas: &mut Canvas, t: f32) {
-        let map = median_filter(&self.map, canvas);
+    fn draw_map(&self, canvas: &mut Canvas, t: f32, alpha: f32) {
+        let map: Vec<f32> = self
+            .last_map
+            .iter()
+            .zip(self.map.iter())
+            .map(|(last, current)| last + (current - last) * alpha)
+            .collect();
+        let map = median_filter(&map, self.width, self.height);
         // let map = &self.map;
 
         for y in 0..canvas.height {
@@ -43,53 +118,76 @@ impl WaveScene {
                 let index = (y * canvas.width + x) as usize;
                 let value = map[index].powf(2.0);
 
-                let hsv = Oklch::new(value.powf(1.0), 0.1, (value + t * 0.1) * 360.0);
-                let rgb = Srgb::from_color(hsv);
-
-                canvas.set_pixel(x, y, rgb.red, rgb.green, rgb.blue);
+                let hue = (value + t * 0.1).rem_euclid(1.0);
+                let [_, a, b] = match &self.palette {
+                    Some(palette) => palette.sample_oklab(hue),
+                    None => {
+                        let (sin, cos) = (hue * TAU).sin_cos();
+                        [0.0, 0.1 * cos, 0.1 * sin]
+                    }
+                };
+                let [r, g, b] = oklab_to_linear([value, a, b]).map(|c| c.clamp(0.0, 1.0));
+
+                canvas.set(x, y, [r, g, b, 1.0]);
             }
         }
     }
 }
 
-fn gen_weights() -> Kernel {
-    let mut weights = [[0.0_f32; KERNEL_SIZE]; KERNEL_SIZE];
-    for y in -SEARCH_RADIUS..SEARCH_RADIUS + 1 {
-        for x in -SEARCH_RADIUS..SEARCH_RADIUS + 1 {
-            let ix = (x + SEARCH_RADIUS) as usize;
-            let iy = (y + SEARCH_RADIUS) as usize;
+fn random_map(width: u32, height: u32) -> Vec<f32> {
+    let mut rng = rand::thread_rng();
+    (0..width * height).map(|_| rng.gen()).collect()
+}
 
-            let dist = (x * x + y * y) as f32;
-            let weight = (1.0 / dist).powf(0.1);
+/// A random value between `a` and `b`, in either order.
+fn random_between(rng: &mut impl Rng, a: f32, b: f32) -> f32 {
+    a + (b - a) * rng.gen::<f32>()
+}
 
-            weights[iy][ix] = weight;
+pub fn gen_weights(radius: i32) -> Kernel {
+    let mut weights = vec![];
+    for y in -radius..radius + 1 {
+        for x in -radius..radius + 1 {
+            let dist = (x * x + y * y) as f32;
+            weights.push((1.0 / dist).powf(0.1));
         }
     }
-    weights
+    Kernel { radius, weights }
 }
 
-fn grow_step(x: u32, y: u32, map: &Vec<f32>, canvas: &Canvas, weights: &Kernel) -> f32 {
+/// Grows a pixel from its neighbors that are above a random level between
+/// `threshold.0` and `threshold.1`.
+pub fn grow_step(
+    x: u32,
+    y: u32,
+    map: &Vec<f32>,
+    width: u32,
+    height: u32,
+    weights: &Kernel,
+    threshold: (f32, f32),
+) -> f32 {
     let mut rng = rand::thread_rng();
 
-    let i = (y * canvas.width + x) as usize;
+    let i = (y * width + x) as usize;
     let mut val = map[i];
 
     let mut n = 0.0;
     let mut c = 0.0;
 
-    for u in -SEARCH_RADIUS..SEARCH_RADIUS + 1 {
-        for v in -SEARCH_RADIUS..SEARCH_RADIUS + 1 {
+    let radius = weights.radius;
+    for u in -radius..radius + 1 {
+        for v in -radius..radius + 1 {
             if u == 0 && v == 0 {
                 continue;
             }
 
-            let x2 = ((x as i32 + u) % canvas.width as i32).abs() as u32;
-            let y2 = ((y as i32 + v) % canvas.height as i32).abs() as u32;
-            let i2 = (y2 * canvas.width + x2) as usize;
+            let x2 = ((x as i32 + u) % width as i32).abs() as u32;
+            let y2 = ((y as i32 + v) % height as i32).abs() as u32;
+            let i2 = (y2 * width + x2) as usize;
             let last_value2 = map[i2];
 
-            if last_value2 > rng.gen_range(0.4..0.6) {
-                let weight = weights[(v + SEARCH_RADIUS) as usize][(u + SEARCH_RADIUS) as usize];
+            if last_value2 > random_between(&mut rng, threshold.0, threshold.1) {
+                let weight = weights.weight(u, v);
 
                 c += last_value2 * rng.gen_range(0.9..1.1) * weight;
                 n += weight;
@@ -101,21 +199,21 @@ fn grow_step(x: u32, y: u32, map: &Vec<f32>, canvas: &Canvas, weights: &Kernel)
     val.clamp(0.0, 1.0)
 }
 
-fn median_filter(map: &Vec<f32>, canvas: &Canvas) -> Vec<f32> {
+pub fn median_filter(map: &Vec<f32>, width: u32, height: u32) -> Vec<f32> {
     const MEDIAN_WINDOW: i32 = 1;
 
-    let mut filtered = vec![0.0; (canvas.width * canvas.height) as usize];
+    let mut filtered = vec![0.0; (width * height) as usize];
     let mut window = Vec::<f32>::new();
 
-    for y in 0..canvas.height {
-        for x in 0..canvas.width {
-            let i = (y * canvas.width + x) as usize;
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as usize;
 
             for u in -MEDIAN_WINDOW..MEDIAN_WINDOW + 1 {
                 for v in -MEDIAN_WINDOW..MEDIAN_WINDOW + 1 {
-                    let x2 = ((x as i32 + u) % canvas.width as i32).abs() as u32;
-                    let y2 = ((y as i32 + v) % canvas.height as i32).abs() as u32;
-                    let i2 = (y2 * canvas.width + x2) as usize;
+                    let x2 = ((x as i32 + u) % width as i32).abs() as u32;
+                    let y2 = ((y as i32 + v) % height as i32).abs() as u32;
+                    let i2 = (y2 * width + x2) as usize;
 
                     let value = map[i2];
                     window.push(value);
@@ -134,28 +232,90 @@ fn median_filter(map: &Vec<f32>, canvas: &Canvas) -> Vec<f32> {
 }
 
 impl Scene for WaveScene {
-    fn tick(&mut self, canvas: &mut Canvas, tick: &FrameTick) {
+    fn name(&self) -> &'static str {
+        "wave"
+    }
+
+    fn description(&self) -> &'static str {
+        "Waves that spread, fade and regrow, cycling through hues."
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.map = random_map(width, height);
+        self.last_map = self.map.clone();
+    }
+
+    fn params(&self) -> &'static [ParamSpec] {
+        &PARAMS
+    }
+
+    fn get_param(&self, name: &str) -> Option<ParamValue> {
+        match name {
+            "speed" => Some(ParamValue::Float(self.speed)),
+            "search_radius" => Some(ParamValue::Int(self.weights.radius as i64)),
+            "decay_min" => Some(ParamValue::Float(self.decay.0)),
+            "decay_max" => Some(ParamValue::Float(self.decay.1)),
+            "grow_min" => Some(ParamValue::Float(self.grow_threshold.0)),
+            "grow_max" => Some(ParamValue::Float(self.grow_threshold.1)),
+            "palette" => Some(param::palette_value(self.palette.as_ref())),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), String> {
+        let value = param::validate(&PARAMS, name, value)?;
+        match name {
+            "speed" => self.speed = value.as_float(),
+            "search_radius" => self.weights = gen_weights(value.as_int() as i32),
+            "decay_min" => self.decay.0 = value.as_float(),
+            "decay_max" => self.decay.1 = value.as_float(),
+            "grow_min" => self.grow_threshold.0 = value.as_float(),
+            "grow_max" => self.grow_threshold.1 = value.as_float(),
+            "palette" => self.palette = gradient::named(value.as_text()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn update_rate(&self) -> UpdateRate {
+        UpdateRate::FixedStep(STEP_RATE)
+    }
+
+    fn step(&mut self, dt: f32) {
         let mut rng = rand::thread_rng();
 
         std::mem::swap(&mut self.last_map, &mut self.map);
         let last_map = &mut self.last_map;
         let map = &mut self.map;
 
-        for y in 0..canvas.height {
-            for x in 0..canvas.width {
-                let i = (y * canvas.width + x) as usize;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = (y * self.width + x) as usize;
                 let last_value = last_map[i];
 
-                map[i] = last_value * (1.0 - (rng.gen_range(0.2..0.4) * tick.dt * self.speed));
+                let decay = random_between(&mut rng, self.decay.0, self.decay.1);
+                map[i] = last_value * (1.0 - (decay * dt * self.speed));
 
                 if last_value <= rng.gen_range(0.1..0.35) {
-                    map[i] = grow_step(x, y, &last_map, canvas, &self.weights);
+                    map[i] = grow_step(
+                        x,
+                        y,
+                        &last_map,
+                        self.width,
+                        self.height,
+                        &self.weights,
+                        self.grow_threshold,
+                    );
                 }
 
                 map[i] = map[i].clamp(0.0, 1.0);
             }
         }
+    }
 
-        self.draw_map(canvas, tick.t * self.speed);
+    fn draw(&mut self, canvas: &mut Canvas, tick: &FrameTick, alpha: f32) {
+        self.draw_map(canvas, tick.t * self.speed, alpha);
     }
 }
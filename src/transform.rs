@@ -0,0 +1,184 @@
+use serde::Deserialize;
+
+use crate::Canvas;
+
+/// Clockwise rotation in 90 degree steps.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "u32")]
+pub enum Rotation {
+    #[default]
+    R0,
+    R90,
+    R180,
+    R270,
+}
+
+impl TryFrom<u32> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u32) -> Result<Self, Self::Error> {
+        match degrees {
+            0 => Ok(Rotation::R0),
+            90 => Ok(Rotation::R90),
+            180 => Ok(Rotation::R180),
+            270 => Ok(Rotation::R270),
+            _ => Err(format!("rotation must be 0, 90, 180 or 270, got {degrees}")),
+        }
+    }
+}
+
+/// A geometric transform from the rendered canvas to what a panel expects,
+/// applied as rotate, then flip, then offset.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub rotation: Rotation,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    /// Shifts the image right and down. Pixels shifted in are transparent.
+    pub offset_x: i32,
+    pub offset_y: i32,
+}
+
+impl Transform {
+    pub fn rotate(rotation: Rotation) -> Self {
+        Transform {
+            rotation,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Transform::default()
+    }
+
+    /// Size of the transformed image for a `width` x `height` source.
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.rotation {
+            Rotation::R0 | Rotation::R180 => (width, height),
+            Rotation::R90 | Rotation::R270 => (height, width),
+        }
+    }
+
+    /// Maps an output pixel back to the source pixel it shows, if any.
+    pub fn source_pixel(&self, x: u32, y: u32, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (out_width, out_height) = self.output_size(width, height);
+
+        let x = x as i32 - self.offset_x;
+        let y = y as i32 - self.offset_y;
+        if x < 0 || y < 0 || x >= out_width as i32 || y >= out_height as i32 {
+            return None;
+        }
+        let (mut x, mut y) = (x as u32, y as u32);
+
+        if self.flip_horizontal {
+            x = out_width - 1 - x;
+        }
+        if self.flip_vertical {
+            y = out_height - 1 - y;
+        }
+
+        Some(match self.rotation {
+            Rotation::R0 => (x, y),
+            Rotation::R90 => (y, height - 1 - x),
+            Rotation::R180 => (width - 1 - x, height - 1 - y),
+            Rotation::R270 => (width - 1 - y, x),
+        })
+    }
+
+    /// Writes the transformed `src` into `dst`, resizing `dst` if needed.
+    pub fn apply(&self, src: &Canvas, dst: &mut Canvas) {
+        let (width, height) = self.output_size(src.width, src.height);
+        if dst.width != width || dst.height != height {
+            *dst = Canvas::new(width, height);
+        }
+
+        for (x, y, px) in dst.enumerate_pixels_mut() {
+            *px = match self.source_pixel(x, y, src.width, src.height) {
+                Some((sx, sy)) => src.pixels[(sy * src.width + sx) as usize],
+                None => [0.0; 4],
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transformed(transform: Transform) -> Vec<Vec<i32>> {
+        let mut dst = Canvas::new(1, 1);
+        transform.apply(&Canvas::numbered(3, 2), &mut dst);
+        dst.indices()
+    }
+
+    #[test]
+    fn rotations() {
+        assert_eq!(transformed(Transform::default()), [[0, 1, 2], [3, 4, 5]]);
+        assert_eq!(
+            transformed(Transform::rotate(Rotation::R90)),
+            [[3, 0], [4, 1], [5, 2]]
+        );
+        assert_eq!(
+            transformed(Transform::rotate(Rotation::R180)),
+            [[5, 4, 3], [2, 1, 0]]
+        );
+        assert_eq!(
+            transformed(Transform::rotate(Rotation::R270)),
+            [[2, 5], [1, 4], [0, 3]]
+        );
+    }
+
+    #[test]
+    fn flips_apply_after_rotating() {
+        let flip_horizontal = Transform {
+            flip_horizontal: true,
+            ..Default::default()
+        };
+        assert_eq!(transformed(flip_horizontal), [[2, 1, 0], [5, 4, 3]]);
+        let flip_vertical = Transform {
+            flip_vertical: true,
+            ..Default::default()
+        };
+        assert_eq!(transformed(flip_vertical), [[3, 4, 5], [0, 1, 2]]);
+        let both = Transform {
+            rotation: Rotation::R90,
+            flip_horizontal: true,
+            ..Default::default()
+        };
+        assert_eq!(transformed(both), [[0, 3], [1, 4], [2, 5]]);
+    }
+
+    #[test]
+    fn offsets_shift_in_transparency() {
+        let transform = Transform {
+            offset_x: 1,
+            offset_y: -1,
+            ..Default::default()
+        };
+        let mut dst = Canvas::new(1, 1);
+        transform.apply(&Canvas::numbered(3, 2), &mut dst);
+        assert_eq!(dst.get(0, 0), Some([0.0; 4]));
+        assert_eq!(dst.get(1, 0), Some([3.0, 0.0, 0.0, 1.0]));
+        assert_eq!(dst.get(2, 0), Some([4.0, 0.0, 0.0, 1.0]));
+        assert_eq!(dst.get(1, 1), Some([0.0; 4]));
+        assert_eq!(transform.source_pixel(0, 0, 3, 2), None);
+        assert_eq!(transform.source_pixel(2, 0, 3, 2), Some((1, 1)));
+    }
+
+    #[test]
+    fn opposite_rotations_round_trip() {
+        let mut turned = Canvas::new(1, 1);
+        let mut back = Canvas::new(1, 1);
+        Transform::rotate(Rotation::R90).apply(&Canvas::numbered(3, 2), &mut turned);
+        Transform::rotate(Rotation::R270).apply(&turned, &mut back);
+        assert_eq!(back.indices(), Canvas::numbered(3, 2).indices());
+    }
+
+    #[test]
+    fn only_right_angles_are_rotations() {
+        assert_eq!(Rotation::try_from(270), Ok(Rotation::R270));
+        assert!(Rotation::try_from(45).is_err());
+        assert!(Rotation::try_from(360).is_err());
+    }
+}